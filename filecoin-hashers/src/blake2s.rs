@@ -19,7 +19,7 @@ use merkletree::{
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
 
-use crate::types::{Domain, HashFunction, Hasher};
+use crate::types::{Domain, HashFunction, HashKind, Hasher};
 
 #[derive(Default, Copy, Clone, PartialEq, Eq, Debug)]
 pub struct Blake2sHasher {}
@@ -28,6 +28,8 @@ impl Hasher for Blake2sHasher {
     type Domain = Blake2sDomain;
     type Function = Blake2sFunction;
 
+    const KIND: HashKind = HashKind::Blake2s;
+
     fn name() -> String {
         "Blake2sHasher".into()
     }
@@ -304,3 +306,93 @@ impl From<Blake2sDomain> for [u8; 32] {
         val.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use generic_array::typenum::U2;
+    use merkletree::{merkle::MerkleTree, store::VecStore};
+
+    fn domain(first_byte: u8) -> Blake2sDomain {
+        let mut bytes = [0u8; 32];
+        bytes[0] = first_byte;
+        Blake2sDomain(bytes)
+    }
+
+    #[test]
+    fn test_path() {
+        let values = [domain(1), domain(1), domain(1), domain(1)];
+
+        let t = MerkleTree::<Blake2sDomain, Blake2sFunction, VecStore<_>, U2>::new(
+            values.iter().copied(),
+        )
+        .expect("merkle tree new failure");
+
+        let p = t.gen_proof(0).expect("gen_proof failure");
+
+        assert_eq!(*p.path(), vec![0, 0]);
+        assert!(p.validate::<Blake2sFunction>().expect("failed to validate"));
+    }
+
+    #[test]
+    fn test_blake2s_hasher() {
+        let leaves = [domain(1), domain(0), domain(0), domain(1)];
+
+        let t = MerkleTree::<Blake2sDomain, Blake2sFunction, VecStore<_>, U2>::new(
+            leaves.iter().copied(),
+        )
+        .expect("merkle tree new failure");
+
+        assert_eq!(t.leafs(), 4);
+
+        let mut a = Blake2sFunction::default();
+
+        for (i, leaf) in leaves.iter().enumerate() {
+            assert_eq!(t.read_at(i).expect("read_at failure"), *leaf);
+        }
+
+        let i1 = a.node(leaves[0], leaves[1], 0);
+        a.reset();
+        let i2 = a.node(leaves[2], leaves[3], 0);
+        a.reset();
+
+        assert_eq!(t.read_at(4).expect("read_at failure"), i1);
+        assert_eq!(t.read_at(5).expect("read_at failure"), i2);
+        assert_eq!(i1, Blake2sFunction::hash2(&leaves[0], &leaves[1]));
+
+        let root = a.node(i1, i2, 1);
+        a.reset();
+
+        assert_eq!(
+            t.read_at(4).expect("read_at failure").0,
+            [
+                202, 32, 184, 206, 49, 233, 134, 192, 190, 107, 21, 84, 17, 80, 177, 108, 235, 26,
+                11, 166, 70, 250, 53, 58, 75, 216, 224, 146, 171, 175, 221, 12
+            ],
+        );
+
+        let expected = [
+            198, 54, 185, 81, 172, 54, 58, 165, 242, 130, 50, 51, 60, 204, 124, 233, 244, 190, 147,
+            213, 103, 251, 15, 39, 54, 201, 2, 173, 73, 66, 138, 37,
+        ];
+        let actual = t.read_at(6).expect("read_at failure").0;
+
+        assert_eq!(actual, expected);
+        assert_eq!(t.read_at(6).expect("read_at failure"), root);
+    }
+
+    #[test]
+    fn test_digest_into_fr() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let mut data = [0u8; 64];
+            rng.fill_bytes(&mut data);
+
+            // Digests are trimmed so that they always are valid field elements.
+            let digest = <Blake2sFunction as HashFunction<Blake2sDomain>>::hash(&data);
+            assert_eq!(digest.0[31] & 0b1100_0000, 0);
+            assert_eq!(Blake2sDomain::from(Fr::from(digest)), digest);
+        }
+    }
+}
@@ -19,7 +19,7 @@ use rand::RngCore;
 use serde::{Deserialize, Serialize};
 
 use crate::types::{
-    Domain, HashFunction, Hasher, PoseidonArity, PoseidonMDArity, POSEIDON_CONSTANTS_16,
+    Domain, HashFunction, HashKind, Hasher, PoseidonArity, PoseidonMDArity, POSEIDON_CONSTANTS_16,
     POSEIDON_CONSTANTS_2, POSEIDON_CONSTANTS_4, POSEIDON_CONSTANTS_8, POSEIDON_MD_CONSTANTS,
 };
 
@@ -30,6 +30,8 @@ impl Hasher for PoseidonHasher {
     type Domain = PoseidonDomain;
     type Function = PoseidonFunction;
 
+    const KIND: HashKind = HashKind::Poseidon;
+
     fn name() -> String {
         "poseidon_hasher".into()
     }
@@ -17,7 +17,7 @@ use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
-use crate::types::{Domain, HashFunction, Hasher};
+use crate::types::{Domain, HashFunction, HashKind, Hasher};
 
 #[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Sha256Hasher {}
@@ -26,6 +26,8 @@ impl Hasher for Sha256Hasher {
     type Domain = Sha256Domain;
     type Function = Sha256Function;
 
+    const KIND: HashKind = HashKind::Sha256;
+
     fn name() -> String {
         "sha256_hasher".into()
     }
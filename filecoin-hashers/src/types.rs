@@ -15,7 +15,7 @@ use merkletree::{
     merkle::Element,
 };
 use rand::RngCore;
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 pub trait Domain:
     Ord
@@ -121,5 +121,16 @@ pub trait Hasher: Clone + Debug + Eq + Default + Send + Sync {
     type Domain: Domain + LightHashable<Self::Function> + AsRef<Self::Domain>;
     type Function: HashFunction<Self::Domain>;
 
+    /// The kind of this hasher, used to tag the proofs built with it.
+    const KIND: HashKind;
+
     fn name() -> String;
 }
+
+/// The hash functions proofs can be built with, selectable at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum HashKind {
+    Poseidon,
+    Sha256,
+    Blake2s,
+}
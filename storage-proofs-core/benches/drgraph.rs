@@ -26,5 +26,33 @@ fn drgraph(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, drgraph);
+fn drgraph_all_parents(c: &mut Criterion) {
+    let n = 1 << 16;
+    let graph = BucketGraph::<PoseidonHasher>::new(n, BASE_DEGREE, 0, [32; 32], ApiVersion::V1_1_0)
+        .unwrap();
+
+    let mut group = c.benchmark_group("all-parents");
+    group.bench_function(format!("bucket/m=6-{}/fresh-buffer", n), |b| {
+        b.iter(|| {
+            for node in 0..n {
+                let mut parents = vec![0; BASE_DEGREE];
+                graph.parents(node, &mut parents).unwrap();
+                black_box(parents);
+            }
+        })
+    });
+    group.bench_function(format!("bucket/m=6-{}/reused-buffer", n), |b| {
+        let mut parents = vec![0; BASE_DEGREE];
+        b.iter(|| {
+            for node in 0..n {
+                graph.parents(node, &mut parents).unwrap();
+                black_box(&parents);
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, drgraph, drgraph_all_parents);
 criterion_main!(benches);
@@ -4,7 +4,9 @@ use filecoin_hashers::{
     poseidon::PoseidonDomain, poseidon::PoseidonHasher, sha256::Sha256Hasher, Domain,
 };
 use rand::{thread_rng, Rng};
-use storage_proofs_core::merkle::{create_base_merkle_tree, BinaryMerkleTree};
+use storage_proofs_core::merkle::{
+    create_base_merkle_tree, gen_proofs_arena, BinaryMerkleTree, MerkleTreeTrait,
+};
 
 fn merkle_benchmark_sha256(c: &mut Criterion) {
     let params = if cfg!(feature = "big-sector-sizes-bench") {
@@ -65,5 +67,40 @@ fn merkle_benchmark_poseidon(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, merkle_benchmark_sha256, merkle_benchmark_poseidon);
+fn merkle_benchmark_gen_proofs(c: &mut Criterion) {
+    let n_nodes = 1024;
+    let mut rng = thread_rng();
+    let mut data: Vec<u8> = Vec::with_capacity(32 * n_nodes);
+    for _ in 0..n_nodes {
+        data.extend(PoseidonDomain::random(&mut rng).into_bytes());
+    }
+    let tree = create_base_merkle_tree::<BinaryMerkleTree<PoseidonHasher>>(None, n_nodes, &data)
+        .expect("failed to create tree");
+    let nodes: Vec<usize> = (0..n_nodes).step_by(4).collect();
+
+    let mut group = c.benchmark_group("merkletree-proofs");
+    group.bench_function("gen_proof", |b| {
+        b.iter(|| {
+            black_box(
+                nodes
+                    .iter()
+                    .map(|&node| tree.gen_proof(node))
+                    .collect::<Result<Vec<_>>>()
+                    .unwrap(),
+            )
+        })
+    });
+    group.bench_function("gen_proofs_arena", |b| {
+        b.iter(|| black_box(gen_proofs_arena(&tree, &nodes).unwrap()))
+    });
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    merkle_benchmark_sha256,
+    merkle_benchmark_poseidon,
+    merkle_benchmark_gen_proofs
+);
 criterion_main!(benches);
@@ -1,25 +1,37 @@
 use std::cmp::{max, min};
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
 use std::fmt::Debug;
+use std::io::{Read, Write};
 use std::marker::PhantomData;
+use std::ops::Deref;
 
-use anyhow::ensure;
+use anyhow::{ensure, format_err};
 use filecoin_hashers::{Hasher, PoseidonArity};
 use fr32::bytes_into_fr_repr_safe;
 use generic_array::typenum::Unsigned;
+use memmap::MmapMut;
 use merkletree::merkle::get_merkle_tree_row_count;
-use rand::{Rng, SeedableRng};
+use rand::{rngs::OsRng, Rng, RngCore, SeedableRng};
 use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
 use crate::{
     api_version::ApiVersion,
     crypto::{derive_porep_domain_seed, DRSAMPLE_DST},
     error::Result,
+    merkle::{
+        coalesce, create_base_merkle_tree_with_node_size, BinaryMerkleTree, CoalescedProofs,
+        MerkleProofTrait, MerkleTreeTrait,
+    },
     parameter_cache::ParameterSetMetadata,
     util::{data_at_node_offset, NODE_SIZE},
     PoRepID,
 };
 
+pub mod presets;
+
 pub const PARALLEL_MERKLE: bool = true;
 
 /// The base degree used for all DRG graphs. One degree from this value is used to ensure that a
@@ -27,6 +39,9 @@ pub const PARALLEL_MERKLE: bool = true;
 /// ordering of the graph nodes.
 pub const BASE_DEGREE: usize = 6;
 
+/// The maximum number of nodes sampled by `BucketGraph::seed_quality`.
+pub const SEED_QUALITY_SAMPLES: usize = 1024;
+
 /// A depth robust graph.
 pub trait Graph<H: Hasher>: Debug + Clone + PartialEq + Eq {
     type Key: Debug;
@@ -76,267 +91,1245 @@ pub trait Graph<H: Hasher>: Debug + Clone + PartialEq + Eq {
         parents_data: &[u8],
         exp_parents_data: Option<&[u8]>,
     ) -> Result<Self::Key>;
-}
 
-pub fn graph_height<U: Unsigned>(number_of_leafs: usize) -> usize {
-    get_merkle_tree_row_count(number_of_leafs, U::to_usize())
-}
+    /// Returns the sorted nodes having `node` as a parent.
+    ///
+    /// All nodes are scanned, as parents may follow their children, e.g. the expansion parents
+    /// of a `StackedBucketGraph`. Use `build_child_index` when the children of many nodes are
+    /// needed.
+    fn children(&self, node: usize) -> Result<Vec<usize>> {
+        ensure!(node < self.size(), "node {} is out of range", node);
+
+        let mut parents = vec![0; self.degree()];
+        let mut children = Vec::new();
+        // Self references mark nodes without parents.
+        for child in (0..self.size()).filter(|&child| child != node) {
+            self.parents(child, &mut parents)?;
+            if parents.iter().any(|&parent| parent as usize == node) {
+                children.push(child);
+            }
+        }
 
-/// Bucket sampling algorithm.
-#[derive(Clone, Debug, PartialEq, Eq, Copy)]
-pub struct BucketGraph<H: Hasher> {
-    nodes: usize,
-    base_degree: usize,
-    seed: [u8; 28],
-    api_version: ApiVersion,
-    _h: PhantomData<H>,
-}
+        Ok(children)
+    }
 
-impl<H: Hasher> ParameterSetMetadata for BucketGraph<H> {
-    fn identifier(&self) -> String {
-        // NOTE: Seed is not included because it does not influence parameter generation.
-        format!(
-            "drgraph::BucketGraph{{size: {}; degree: {}; hasher: {}}}",
-            self.nodes,
-            self.degree(),
-            H::name(),
-        )
+    /// Returns the sorted children of every node, computed in a single pass over the graph.
+    fn build_child_index(&self) -> Result<Vec<Vec<usize>>> {
+        Ok(reverse_adjacency(self)?
+            .into_iter()
+            .map(|children| children.into_iter().map(|child| child as usize).collect())
+            .collect())
     }
 
-    fn sector_size(&self) -> u64 {
-        (self.nodes * NODE_SIZE) as u64
+    /// Returns all nodes whose labels depend, directly or transitively, on the given node.
+    fn descendants(&self, node: usize) -> Result<BTreeSet<usize>> {
+        ensure!(node < self.size(), "node {} is out of range", node);
+
+        let children = reverse_adjacency(self)?;
+        let mut descendants = BTreeSet::new();
+        let mut stack = vec![node];
+        while let Some(current) = stack.pop() {
+            for &child in &children[current] {
+                if descendants.insert(child as usize) {
+                    stack.push(child as usize);
+                }
+            }
+        }
+
+        Ok(descendants)
     }
-}
 
-impl<H: Hasher> Graph<H> for BucketGraph<H> {
-    type Key = H::Domain;
+    /// Builds a binary merkle tree over the leaves of the parents of `node`, in sorted parent
+    /// order. The tree is padded with default leaves to the next power of two.
+    fn parents_merkle_tree(&self, node: usize, leaves: &[H::Domain]) -> Result<BinaryMerkleTree<H>>
+    where
+        H: 'static,
+    {
+        ensure!(node < self.size(), "node {} is out of range", node);
+        ensure!(
+            leaves.len() == self.size(),
+            "expected {} leaves, got {}",
+            self.size(),
+            leaves.len()
+        );
 
-    fn create_key(
+        let mut parents = vec![0; self.degree()];
+        self.parents(node, &mut parents)?;
+        parents.sort_unstable();
+
+        let mut parent_leaves: Vec<H::Domain> = parents
+            .iter()
+            .map(|&parent| leaves[parent as usize])
+            .collect();
+        parent_leaves.resize(
+            parent_leaves.len().next_power_of_two().max(2),
+            H::Domain::default(),
+        );
+
+        BinaryMerkleTree::new(parent_leaves)
+    }
+
+    /// Returns the root of the tree built by `parents_merkle_tree`.
+    fn parents_merkle_root(&self, node: usize, leaves: &[H::Domain]) -> Result<H::Domain>
+    where
+        H: 'static,
+    {
+        Ok(self.parents_merkle_tree(node, leaves)?.root())
+    }
+
+    /// Checks that the node at `index` of `data`, split into nodes of `node_size` bytes, is
+    /// consistent with `expected_root`. See `create_base_merkle_tree_with_node_size` for the
+    /// supported node sizes.
+    ///
+    /// The binary tree over `data` is rebuilt locally and a freshly generated proof is validated,
+    /// so no proof needs to be received from the prover.
+    fn verify_inclusion(
         &self,
-        id: &H::Domain,
+        data: &[u8],
+        node_size: usize,
+        index: usize,
+        expected_root: &H::Domain,
+    ) -> Result<bool>
+    where
+        H: 'static,
+    {
+        ensure!(
+            data.len() == self.size() * node_size,
+            "expected {} bytes of data, got {}",
+            self.size() * node_size,
+            data.len()
+        );
+        ensure!(index < self.size(), "node {} is out of range", index);
+
+        let tree =
+            create_base_merkle_tree_with_node_size::<BinaryMerkleTree<H>>(None, data, node_size)?;
+        let proof = tree.gen_proof(index)?;
+
+        Ok(proof.root() == *expected_root && proof.validate(index))
+    }
+
+    /// Builds the binary tree over the nodes stored in `mmap`, split into nodes of `node_size`
+    /// bytes. The mapping must hold exactly one node per graph node.
+    ///
+    /// See `create_base_merkle_tree_with_node_size` for the supported node sizes.
+    fn merkle_tree_from_mmap(&self, mmap: &MmapMut, node_size: usize) -> Result<BinaryMerkleTree<H>>
+    where
+        H: 'static,
+    {
+        let data: &[u8] = mmap.as_ref();
+        ensure!(
+            data.len() == self.size() * node_size,
+            "expected {} bytes of data, got {}",
+            self.size() * node_size,
+            data.len()
+        );
+
+        create_base_merkle_tree_with_node_size::<BinaryMerkleTree<H>>(None, data, node_size)
+    }
+
+    /// Proves that `node` and all of its parents are included in `tree`, with the path levels
+    /// shared between the proofs stored only once.
+    fn gen_node_and_parents_proof<Tree: MerkleTreeTrait<Hasher = H>>(
+        &self,
+        tree: &Tree,
         node: usize,
-        parents: &[u32],
-        base_parents_data: &[u8],
-        _exp_parents_data: Option<&[u8]>,
-    ) -> Result<Self::Key> {
-        let mut hasher = Sha256::new();
-        hasher.update(AsRef::<[u8]>::as_ref(id));
+    ) -> Result<NodeParentsProof<H>> {
+        let mut parents = vec![0; self.degree()];
+        self.parents(node, &mut parents)?;
 
-        // The hash is about the parents, hence skip if a node doesn't have any parents
-        if node != parents[0] as usize {
-            for parent in parents.iter() {
-                let offset = data_at_node_offset(*parent as usize);
-                hasher.update(&base_parents_data[offset..offset + NODE_SIZE]);
-            }
-        }
+        let proofs = std::iter::once(node)
+            .chain(parents.into_iter().map(|parent| parent as usize))
+            .map(|i| Ok((tree.gen_proof(i)?, i)))
+            .collect::<Result<Vec<_>>>()?;
 
-        let hash = hasher.finalize();
-        Ok(bytes_into_fr_repr_safe(hash.as_ref()).into())
+        Ok(NodeParentsProof {
+            proofs: coalesce(&proofs)?,
+        })
     }
 
-    #[inline]
-    fn parents(&self, node: usize, parents: &mut [u32]) -> Result<()> {
-        let m = self.degree();
+    /// Heuristically estimates a lower bound on the cumulative pebbling complexity of the graph.
+    ///
+    /// Each of the `samples` rounds removes every node with probability 1/4 and measures the
+    /// depth of the remaining graph. If removing `e` nodes leaves depth `d`, pebbling requires on
+    /// the order of `e * d` space-time, the estimate is the mean of `e * d` over all rounds. The
+    /// removals are drawn from an RNG seeded with the graph seed, so for a given graph and
+    /// `samples` the result is deterministic. Parents are assumed to precede their children.
+    ///
+    /// This is a research diagnostic, not a proof of any bound.
+    fn pebbling_lower_bound_estimate(&self, samples: usize) -> Result<f64> {
+        ensure!(samples > 0, "samples must be greater than zero");
 
-        match node {
-            // There are special cases for the first and second node: the first node self
-            // references, the second node only references the first node.
-            0 | 1 => {
-                // Use the degree of the current graph (`m`) as `parents.len()` might be bigger than
-                // that (that's the case for Stacked Graph).
-                for parent in parents.iter_mut().take(m) {
-                    *parent = 0;
+        let mut all_parents = vec![0; self.size() * self.degree()];
+        for (node, parents) in all_parents.chunks_mut(self.degree().max(1)).enumerate() {
+            self.parents(node, parents)?;
+        }
+
+        let mut seed = [0u8; 32];
+        seed[..28].copy_from_slice(&self.seed());
+        let mut rng = ChaCha8Rng::from_seed(seed);
+
+        let mut depths = vec![0usize; self.size()];
+        let mut total = 0f64;
+        for _ in 0..samples {
+            let mut removed = 0;
+            let mut max_depth = 0;
+            for node in 0..self.size() {
+                if rng.gen_ratio(1, 4) {
+                    removed += 1;
+                    depths[node] = 0;
+                    continue;
                 }
-                Ok(())
+
+                let parents = &all_parents[node * self.degree()..(node + 1) * self.degree()];
+                let deepest_parent = parents
+                    .iter()
+                    .map(|&parent| parent as usize)
+                    .filter(|&parent| parent < node)
+                    .map(|parent| depths[parent])
+                    .max()
+                    .unwrap_or(0);
+                depths[node] = deepest_parent + 1;
+                max_depth = max(max_depth, depths[node]);
             }
-            _ => {
-                // DRG node indexes are guaranteed to fit within a `u32`.
-                let node = node as u32;
-
-                let mut seed = [0u8; 32];
-                seed[..28].copy_from_slice(&self.seed);
-                seed[28..].copy_from_slice(&node.to_le_bytes());
-                let mut rng = ChaCha8Rng::from_seed(seed);
-
-                let m_prime = m - 1;
-                // Large sector sizes require that metagraph node indexes are `u64`.
-                let metagraph_node = node as u64 * m_prime as u64;
-                let n_buckets = (metagraph_node as f64).log2().ceil() as u64;
-
-                let (predecessor_index, other_drg_parents) = match self.api_version {
-                    ApiVersion::V1_0_0 => (m_prime, &mut parents[..]),
-                    ApiVersion::V1_1_0 => (0, &mut parents[1..]),
-                };
+            total += (removed * max_depth) as f64;
+        }
+
+        Ok(total / samples as f64)
+    }
 
-                for parent in other_drg_parents.iter_mut().take(m_prime) {
-                    let bucket_index = (rng.gen::<u64>() % n_buckets) + 1;
-                    let largest_distance_in_bucket = min(metagraph_node, 1 << bucket_index);
-                    let smallest_distance_in_bucket = max(2, largest_distance_in_bucket >> 1);
+    /// Checks that the parents of every node are in range and precede the node, or, for nodes
+    /// without parents, all reference the node itself. Fails naming the first offending node.
+    ///
+    /// Parents are not required to be sorted, `BucketGraph` lists the immediate predecessor
+    /// first. Graphs with parents in another layer must override this check.
+    fn verify_parents(&self) -> Result<()> {
+        let mut parents = vec![0; self.degree()];
+        for node in 0..self.size() {
+            self.parents(node, &mut parents)?;
+
+            if let Some(parent) = parents.iter().find(|&&p| p as usize >= self.size()) {
+                return Err(format_err!(
+                    "node {} has the out of range parent {}",
+                    node,
+                    parent
+                ));
+            }
+            let without_parents = parents.iter().all(|&p| p as usize == node);
+            if !without_parents {
+                if let Some(parent) = parents.iter().find(|&&p| p as usize >= node) {
+                    return Err(format_err!(
+                        "node {} has the parent {}, which does not precede it",
+                        node,
+                        parent
+                    ));
+                }
+            }
+        }
 
-                    // Add 1 becuase the number of distances in the bucket is inclusive.
-                    let n_distances_in_bucket =
-                        largest_distance_in_bucket - smallest_distance_in_bucket + 1;
+        Ok(())
+    }
 
-                    let distance =
-                        smallest_distance_in_bucket + (rng.gen::<u64>() % n_distances_in_bucket);
+    /// Computes the parents of every node once, for repeated lookups through a `ParentTable`.
+    fn parent_table(&self) -> Result<ParentTable> {
+        let degree = self.degree();
+        ensure!(degree > 0, "the graph has no parents");
 
-                    let metagraph_parent = metagraph_node - distance;
+        let mut parents = vec![0; self.size() * degree];
+        for (node, node_parents) in parents.chunks_mut(degree).enumerate() {
+            self.parents(node, node_parents)?;
+        }
 
-                    // Any metagraph node mapped onto the DRG can be safely cast back to `u32`.
-                    let mapped_parent = (metagraph_parent / m_prime as u64) as u32;
+        Ok(ParentTable { degree, parents })
+    }
 
-                    *parent = if mapped_parent == node {
-                        node - 1
-                    } else {
-                        mapped_parent
-                    };
-                }
+    /// Returns true if both graphs have the same size, degree and parents for every node, no
+    /// matter how they were parameterized. Stops at the first difference.
+    fn adjacency_equal<G: Graph<H>>(&self, other: &G) -> Result<bool> {
+        if self.size() != other.size() || self.degree() != other.degree() {
+            return Ok(false);
+        }
 
-                // Immediate predecessor must be the first parent, so hashing cannot begin early.
-                parents[predecessor_index] = node - 1;
-                Ok(())
+        let mut parents = vec![0; self.degree()];
+        let mut other_parents = vec![0; other.degree()];
+        for node in 0..self.size() {
+            self.parents(node, &mut parents)?;
+            other.parents(node, &mut other_parents)?;
+            if parents != other_parents {
+                return Ok(false);
             }
         }
+
+        Ok(true)
     }
 
-    #[inline]
-    fn size(&self) -> usize {
-        self.nodes
+    /// Returns the number of distinct edges divided by the number of possible directed edges.
+    ///
+    /// Repeated parents and the self references of nodes without parents are not counted.
+    fn density(&self) -> Result<f64> {
+        ensure!(self.size() > 1, "density needs at least two nodes");
+
+        let edges: usize = reverse_adjacency(self)?.iter().map(Vec::len).sum();
+        Ok(edges as f64 / (self.size() * (self.size() - 1)) as f64)
     }
 
-    /// Returns the degree of the graph.
-    #[inline]
-    fn degree(&self) -> usize {
-        self.base_degree
+    /// Writes the parents of all nodes in a compact encoding, readable by `read_adjacency_delta`.
+    ///
+    /// The header holds the number of nodes and the degree as little endian `u64` and `u32`. The
+    /// sorted parents of every node follow, the first one as a little endian `u32`, the others as
+    /// varint encoded deltas to their predecessor.
+    fn write_adjacency_delta<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(&(self.size() as u64).to_le_bytes())?;
+        w.write_all(&(self.degree() as u32).to_le_bytes())?;
+
+        let mut parents = vec![0; self.degree()];
+        let mut buf = Vec::with_capacity(self.degree() * 5);
+        for node in 0..self.size() {
+            self.parents(node, &mut parents)?;
+            parents.sort_unstable();
+
+            buf.clear();
+            if let Some(first) = parents.first() {
+                buf.extend_from_slice(&first.to_le_bytes());
+            }
+            for pair in parents.windows(2) {
+                write_varint(&mut buf, pair[1] - pair[0]);
+            }
+            w.write_all(&buf)?;
+        }
+
+        Ok(())
     }
+}
 
-    fn seed(&self) -> [u8; 28] {
-        self.seed
+/// Reads an adjacency written by `Graph::write_adjacency_delta`, returning the sorted parents of
+/// every node.
+///
+/// The header is untrusted, so the adjacency only grows as the parents are read and a header
+/// claiming more parents than the input holds fails once the input is exhausted.
+pub fn read_adjacency_delta<R: Read>(r: &mut R) -> Result<Vec<Vec<u32>>> {
+    let mut nodes = [0u8; 8];
+    r.read_exact(&mut nodes)?;
+    let mut degree = [0u8; 4];
+    r.read_exact(&mut degree)?;
+    let nodes = u64::from_le_bytes(nodes) as usize;
+    let degree = u32::from_le_bytes(degree) as usize;
+
+    let mut adjacency = Vec::new();
+    for _ in 0..nodes {
+        let mut parents = Vec::new();
+        if degree > 0 {
+            let mut first = [0u8; 4];
+            r.read_exact(&mut first)?;
+            parents.push(u32::from_le_bytes(first));
+        }
+        for i in 1..degree {
+            let delta = read_varint(r)?;
+            let parent = parents[i - 1]
+                .checked_add(delta)
+                .ok_or_else(|| format_err!("invalid parent delta"))?;
+            parents.push(parent);
+        }
+        adjacency.push(parents);
     }
 
-    fn new(
-        nodes: usize,
-        base_degree: usize,
-        expansion_degree: usize,
-        porep_id: PoRepID,
-        api_version: ApiVersion,
-    ) -> Result<Self> {
-        ensure!(expansion_degree == 0, "Expension degree must be zero.");
+    Ok(adjacency)
+}
 
-        // The number of metagraph nodes must be less than `2u64^54` as to not incur rounding errors
-        // when casting metagraph node indexes from `u64` to `f64` during parent generation.
-        let m_prime = base_degree - 1;
-        let n_metagraph_nodes = nodes as u64 * m_prime as u64;
+/// LEB128 encoding of `value`.
+pub(crate) fn write_varint(buf: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        buf.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+pub(crate) fn read_varint<R: Read>(r: &mut R) -> Result<u32> {
+    let mut value = 0u32;
+    for shift in (0..32).step_by(7) {
+        let mut byte = [0u8; 1];
+        r.read_exact(&mut byte)?;
+        // Only the low 4 bits of the fifth byte fit into a `u32`.
         ensure!(
-            n_metagraph_nodes <= 1u64 << 54,
-            "The number of metagraph nodes must be precisely castable to `f64`"
+            shift < 28 || byte[0] & 0x7f <= 0x0f,
+            "varint overflows a u32"
         );
+        value |= u32::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
 
-        let drg_seed = derive_drg_seed(porep_id);
+    Err(format_err!("varint is too long"))
+}
 
-        Ok(BucketGraph {
-            nodes,
-            base_degree,
-            seed: drg_seed,
-            api_version,
-            _h: PhantomData,
-        })
+/// Returns, for every node, the sorted and deduplicated list of nodes having it as a parent.
+fn reverse_adjacency<H: Hasher, G: Graph<H>>(graph: &G) -> Result<Vec<Vec<u32>>> {
+    let mut children = vec![Vec::new(); graph.size()];
+    let mut parents = vec![0; graph.degree()];
+    for node in 0..graph.size() {
+        graph.parents(node, &mut parents)?;
+        for &parent in &parents {
+            // Self references mark nodes without parents.
+            if parent as usize != node {
+                children[parent as usize].push(node as u32);
+            }
+        }
     }
+
+    // Nodes are visited in increasing order, so each list is already sorted.
+    for list in children.iter_mut() {
+        list.dedup();
+    }
+
+    Ok(children)
 }
 
-pub fn derive_drg_seed(porep_id: PoRepID) -> [u8; 28] {
-    let mut drg_seed = [0; 28];
-    let raw_seed = derive_porep_domain_seed(DRSAMPLE_DST, porep_id);
-    drg_seed.copy_from_slice(&raw_seed[..28]);
-    drg_seed
+/// Proves the inclusion of a node and of all of its parents, as generated by
+/// `Graph::gen_node_and_parents_proof`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct NodeParentsProof<H: Hasher> {
+    proofs: CoalescedProofs<H>,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl<H: Hasher> NodeParentsProof<H> {
+    /// Validates that exactly `node` followed by its parents in `graph` are proven against
+    /// `expected_root`.
+    pub fn validate<G: Graph<H>>(&self, graph: &G, node: usize, expected_root: &H::Domain) -> bool {
+        let mut parents = vec![0; graph.degree()];
+        if graph.parents(node, &mut parents).is_err() {
+            return false;
+        }
 
-    use filecoin_hashers::{
-        blake2s::Blake2sHasher, poseidon::PoseidonHasher, sha256::Sha256Hasher,
-    };
-    use generic_array::typenum::{U0, U2, U4, U8};
-    use memmap::{MmapMut, MmapOptions};
-    use merkletree::store::StoreConfig;
+        let expected_nodes = std::iter::once(node).chain(parents.into_iter().map(|p| p as usize));
+        self.proofs.nodes().eq(expected_nodes) && self.proofs.validate_all(expected_root)
+    }
+}
 
-    use crate::merkle::{
-        create_base_merkle_tree, DiskStore, MerkleProofTrait, MerkleTreeTrait, MerkleTreeWrapper,
-    };
+/// The parents of all nodes of a graph, stored contiguously with `degree` parents per node.
+///
+/// Built by `Graph::parent_table`. Unlike the disk backed parent cache of stacked graphs, the
+/// table is only held in memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParentTable {
+    degree: usize,
+    parents: Vec<u32>,
+}
 
-    // Create and return an object of MmapMut backed by in-memory copy of data.
-    pub fn mmap_from(data: &[u8]) -> MmapMut {
-        let mut mm = MmapOptions::new()
-            .len(data.len())
-            .map_anon()
-            .expect("Failed to create memory map");
-        mm.copy_from_slice(data);
-        mm
+impl ParentTable {
+    /// Returns the number of nodes.
+    pub fn len(&self) -> usize {
+        self.parents.len() / self.degree
     }
 
-    fn graph_bucket<H: Hasher>() {
-        // These PoRepIDs do not correspond to the small-sized graphs used in
-        // the tests. However, they are sufficient to distinguish legacy vs new
-        // behavior of parent ordering.
-        let porep_id = |id: u8| {
-            let mut porep_id = [0u8; 32];
-            porep_id[0] = id;
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
 
-            porep_id
-        };
+    pub fn degree(&self) -> usize {
+        self.degree
+    }
 
-        let legacy_porep_id = porep_id(0);
-        let new_porep_id = porep_id(5);
+    /// Returns the parents of `node`, as written by `Graph::parents`.
+    ///
+    /// Panics if `node` is out of range.
+    pub fn parents(&self, node: usize) -> &[u32] {
+        &self.parents[node * self.degree..(node + 1) * self.degree]
+    }
+}
 
-        graph_bucket_aux::<H>(legacy_porep_id, ApiVersion::V1_0_0);
-        graph_bucket_aux::<H>(new_porep_id, ApiVersion::V1_1_0);
+/// Wraps a graph together with its merkle tree depth for arity `U`, which is computed once at
+/// construction instead of on every `Graph::merkle_tree_depth` call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DepthCachedGraph<H: Hasher, G: Graph<H>, U: PoseidonArity> {
+    graph: G,
+    depth: u64,
+    _h: PhantomData<(H, U)>,
+}
+
+impl<H: Hasher, G: Graph<H>, U: 'static + PoseidonArity> DepthCachedGraph<H, G, U> {
+    pub fn new(graph: G) -> Self {
+        let depth = graph.merkle_tree_depth::<U>();
+        DepthCachedGraph {
+            graph,
+            depth,
+            _h: PhantomData,
+        }
     }
 
-    fn graph_bucket_aux<H: Hasher>(porep_id: PoRepID, api_version: ApiVersion) {
-        let degree = BASE_DEGREE;
+    /// Returns the cached `Graph::merkle_tree_depth` of the wrapped graph.
+    pub fn merkle_tree_depth(&self) -> u64 {
+        self.depth
+    }
 
-        for &size in &[4, 16, 256, 2048] {
-            let g = BucketGraph::<H>::new(size, degree, 0, porep_id, api_version)
-                .expect("bucket graph new failed");
+    pub fn into_inner(self) -> G {
+        self.graph
+    }
+}
 
-            assert_eq!(g.size(), size, "wrong nodes count");
+impl<H: Hasher, G: Graph<H>, U: PoseidonArity> Deref for DepthCachedGraph<H, G, U> {
+    type Target = G;
 
-            let mut parents = vec![0; degree];
-            g.parents(0, &mut parents).expect("parents failed");
-            assert_eq!(parents, vec![0; degree as usize]);
-            parents = vec![0; degree];
-            g.parents(1, &mut parents).expect("parents failed");
-            assert_eq!(parents, vec![0; degree as usize]);
+    fn deref(&self) -> &G {
+        &self.graph
+    }
+}
 
-            for i in 1..size {
-                let mut pa1 = vec![0; degree];
-                g.parents(i, &mut pa1).expect("parents failed");
-                let mut pa2 = vec![0; degree];
-                g.parents(i, &mut pa2).expect("parents failed");
+/// A graph with the edges of the wrapped graph reversed and its nodes mirrored, so that node `i`
+/// has the parents `size - 1 - p` for all parents `p` of node `size - 1 - i`. All parents of a
+/// node then follow it, as needed by backward passes.
+///
+/// This is deliberately not a `Graph`, whose provided methods rely on parents preceding their
+/// children.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReverseGraph<H: Hasher, G: Graph<H>> {
+    graph: G,
+    _h: PhantomData<H>,
+}
 
-                assert_eq!(pa1.len(), degree);
-                assert_eq!(pa1, pa2, "different parents on the same node");
+impl<H: Hasher, G: Graph<H>> ReverseGraph<H, G> {
+    pub fn new(graph: G) -> Self {
+        ReverseGraph {
+            graph,
+            _h: PhantomData,
+        }
+    }
 
-                let mut p1 = vec![0; degree];
-                g.parents(i, &mut p1).expect("parents failed");
-                let mut p2 = vec![0; degree];
-                g.parents(i, &mut p2).expect("parents failed");
+    /// Writes the parents of `node`, in the order of the parents of the mirrored node.
+    ///
+    /// Mirrored nodes without parents reference themselves, so their reversed node does too.
+    pub fn parents(&self, node: usize, parents: &mut [u32]) -> Result<()> {
+        ensure!(node < self.size(), "node {} is out of range", node);
+
+        let last = (self.size() - 1) as u32;
+        self.graph.parents(last as usize - node, parents)?;
+        for parent in parents.iter_mut().take(self.degree()) {
+            *parent = last - *parent;
+        }
 
-                for parent in p1 {
-                    // TODO: fix me
-                    assert_ne!(i, parent as usize, "self reference found");
-                }
+        Ok(())
+    }
 
-                match api_version {
-                    ApiVersion::V1_0_0 => {
-                        assert_eq!(
-                            i - 1,
-                            pa1[degree - 1] as usize,
-                            "immediate predecessor was not last DRG parent"
-                        );
-                    }
+    pub fn size(&self) -> usize {
+        self.graph.size()
+    }
+
+    pub fn degree(&self) -> usize {
+        self.graph.degree()
+    }
+
+    pub fn seed(&self) -> [u8; 28] {
+        self.graph.seed()
+    }
+
+    /// Returns the merkle tree depth, which is the one of the wrapped graph.
+    pub fn merkle_tree_depth<U: 'static + PoseidonArity>(&self) -> u64 {
+        self.graph.merkle_tree_depth::<U>()
+    }
+
+    pub fn into_inner(self) -> G {
+        self.graph
+    }
+}
+
+pub fn graph_height<U: Unsigned>(number_of_leafs: usize) -> usize {
+    get_merkle_tree_row_count(number_of_leafs, U::to_usize())
+}
+
+/// Controls the parents of the first two nodes, which are too close to the start of the graph
+/// to be bucket sampled.
+#[derive(Clone, Debug, PartialEq, Eq, Copy)]
+pub enum BoundaryPolicy {
+    /// All parents of nodes 0 and 1 are node 0: node 0 self references (it has no parents) and
+    /// node 1 only references node 0.
+    Zeroed,
+    /// Nodes 0 and 1 both self reference, so neither of them has parents.
+    SelfReferenced,
+}
+
+impl Default for BoundaryPolicy {
+    fn default() -> Self {
+        BoundaryPolicy::Zeroed
+    }
+}
+
+/// Controls how the graph seed is turned into the seeds of the rngs sampling the parents of every
+/// node, see `BucketGraph::node_rng_seed`.
+#[derive(Clone, Debug, PartialEq, Eq, Copy)]
+pub enum SeedPolicy {
+    /// The graph seed is used as is, so graphs of different degrees with the same seed draw from
+    /// the same randomness.
+    Shared,
+    /// The base degree is hashed into the graph seed first, so graphs of different degrees with
+    /// the same seed sample independent parents.
+    DegreeSeparated,
+}
+
+impl Default for SeedPolicy {
+    fn default() -> Self {
+        SeedPolicy::Shared
+    }
+}
+
+/// Bucket sampling algorithm.
+#[derive(Clone, Debug, PartialEq, Eq, Copy)]
+pub struct BucketGraph<H: Hasher> {
+    nodes: usize,
+    base_degree: usize,
+    seed: [u8; 28],
+    api_version: ApiVersion,
+    boundary_policy: BoundaryPolicy,
+    seed_policy: SeedPolicy,
+    /// The seed the per node rng seeds are derived from, depending on `seed_policy`.
+    rng_seed: [u8; 28],
+    _h: PhantomData<H>,
+}
+
+impl<H: Hasher> BucketGraph<H> {
+    /// Creates a graph from an already derived DRG seed, see `derive_drg_seed`.
+    pub fn from_seed(
+        nodes: usize,
+        base_degree: usize,
+        seed: [u8; 28],
+        api_version: ApiVersion,
+    ) -> Result<Self> {
+        let min_nodes = min_nodes_for_degree(base_degree)
+            .ok_or_else(|| format_err!("The base degree must be at least 2"))?;
+        ensure!(
+            nodes >= min_nodes,
+            "The graph must have at least {} nodes",
+            min_nodes
+        );
+        // Parents are stored as `u32`, see `Graph::parents`.
+        ensure!(
+            u32::try_from(nodes - 1).is_ok(),
+            "DRG node indexes must fit within a `u32`"
+        );
+
+        // The number of metagraph nodes must be less than `2u64^54` as to not incur rounding errors
+        // when casting metagraph node indexes from `u64` to `f64` during parent generation.
+        let m_prime = base_degree - 1;
+        let n_metagraph_nodes = nodes as u64 * m_prime as u64;
+        ensure!(
+            n_metagraph_nodes <= 1u64 << 54,
+            "The number of metagraph nodes must be precisely castable to `f64`"
+        );
+
+        Ok(BucketGraph {
+            nodes,
+            base_degree,
+            seed,
+            api_version,
+            boundary_policy: BoundaryPolicy::default(),
+            seed_policy: SeedPolicy::default(),
+            rng_seed: seed,
+            _h: PhantomData,
+        })
+    }
+
+    /// Returns this graph with the given handling of nodes 0 and 1.
+    pub fn with_boundary_policy(mut self, boundary_policy: BoundaryPolicy) -> Self {
+        self.boundary_policy = boundary_policy;
+        self
+    }
+
+    pub fn boundary_policy(&self) -> BoundaryPolicy {
+        self.boundary_policy
+    }
+
+    /// Returns this graph with the given derivation of the per node rng seeds.
+    pub fn with_seed_policy(mut self, seed_policy: SeedPolicy) -> Self {
+        self.seed_policy = seed_policy;
+        self.rng_seed = match seed_policy {
+            SeedPolicy::Shared => self.seed,
+            SeedPolicy::DegreeSeparated => {
+                let mut hasher = Sha256::new();
+                hasher.update(&self.seed);
+                hasher.update(&(self.base_degree as u64).to_le_bytes());
+
+                let mut rng_seed = [0; 28];
+                rng_seed.copy_from_slice(&hasher.finalize()[..28]);
+                rng_seed
+            }
+        };
+        self
+    }
+
+    pub fn seed_policy(&self) -> SeedPolicy {
+        self.seed_policy
+    }
+
+    /// Returns the seed of the ChaCha8 rng used to sample the parents of `node`: the 28 bytes of
+    /// the graph seed followed by `node` as a little endian `u32`.
+    ///
+    /// With `SeedPolicy::DegreeSeparated`, the graph seed is replaced by the first 28 bytes of
+    /// `Sha256(seed | base_degree)`, the degree encoded as a little endian `u64`.
+    ///
+    /// Nodes 0 and 1 have fixed parents, see `BoundaryPolicy`, and never use their seed.
+    pub fn node_rng_seed(&self, node: usize) -> [u8; 32] {
+        let node = u32::try_from(node).expect("DRG node indexes must fit within a `u32`");
+
+        let mut seed = [0u8; 32];
+        seed[..28].copy_from_slice(&self.rng_seed);
+        seed[28..].copy_from_slice(&node.to_le_bytes());
+        seed
+    }
+
+    /// Samples the parents of a node from 2 on, see `Graph::parents`. Returns how many sampled
+    /// parents were the node itself and got replaced by its immediate predecessor.
+    #[inline]
+    fn sample_parents(&self, node: usize, parents: &mut [u32]) -> usize {
+        let m_prime = self.degree() - 1;
+
+        let mut fallbacks = 0;
+        let (predecessor_index, other_drg_parents) = match self.api_version {
+            ApiVersion::V1_0_0 => (m_prime, &mut parents[..]),
+            ApiVersion::V1_1_0 => (0, &mut parents[1..]),
+        };
+
+        for (parent, edge) in other_drg_parents
+            .iter_mut()
+            .take(m_prime)
+            .zip(self.sample_edges(node))
+        {
+            if edge.raw_parent != edge.final_parent {
+                fallbacks += 1;
+            }
+            *parent = edge.final_parent;
+        }
+
+        // Immediate predecessor must be the first parent, so hashing cannot begin early.
+        parents[predecessor_index] = (node - 1) as u32;
+
+        fallbacks
+    }
+
+    /// Lazily draws the `degree - 1` bucket sampled parents of a node from 2 on, consuming the rng
+    /// of the node only as far as the iterator is advanced.
+    #[inline]
+    fn sample_edges(&self, node: usize) -> impl Iterator<Item = SampledEdge> {
+        let m_prime = self.degree() - 1;
+
+        let mut rng = ChaCha8Rng::from_seed(self.node_rng_seed(node));
+
+        // `from_seed` guarantees that the indexes of the graph nodes fit within a `u32`.
+        let node = u32::try_from(node).expect("DRG node indexes must fit within a `u32`");
+
+        // Large sector sizes require that metagraph node indexes are `u64`.
+        let metagraph_node = node as u64 * m_prime as u64;
+        let n_buckets = (metagraph_node as f64).log2().ceil() as u64;
+
+        // There are at most 54 buckets, as `from_seed` bounds the metagraph by `2^54` nodes, so
+        // the distances below cannot overflow a `u64`.
+        debug_assert!(n_buckets <= 54, "too many buckets: {}", n_buckets);
+        (0..m_prime).map(move |_| {
+            let bucket_index = (rng.gen::<u64>() % n_buckets) + 1;
+            let largest_distance_in_bucket = min(metagraph_node, 1u64 << bucket_index);
+            let smallest_distance_in_bucket = max(2, largest_distance_in_bucket >> 1);
+
+            // Add 1 becuase the number of distances in the bucket is inclusive.
+            let n_distances_in_bucket =
+                largest_distance_in_bucket - smallest_distance_in_bucket + 1;
+
+            let distance = smallest_distance_in_bucket + (rng.gen::<u64>() % n_distances_in_bucket);
+
+            let metagraph_parent = metagraph_node - distance;
+
+            // Any metagraph node mapped onto the DRG can be safely cast back to `u32`.
+            let mapped_parent = (metagraph_parent / m_prime as u64) as u32;
+
+            SampledEdge {
+                meta_index: metagraph_parent,
+                back_dist: distance,
+                raw_parent: mapped_parent,
+                final_parent: if mapped_parent == node {
+                    node - 1
+                } else {
+                    mapped_parent
+                },
+            }
+        })
+    }
+
+    /// Returns the bucket sampled draws of the parents of `node`, in the order they are drawn,
+    /// including the intermediate metagraph values. The immediate predecessor, which is always a
+    /// parent as well, is not drawn and therefore not included.
+    ///
+    /// Nodes 0 and 1 have fixed parents, see `BoundaryPolicy`, so `node` must be at least 2.
+    pub fn sampled_edges(&self, node: usize) -> Result<Vec<SampledEdge>> {
+        ensure!(
+            (2..self.nodes).contains(&node),
+            "node {} has no sampled parents",
+            node
+        );
+
+        Ok(self.sample_edges(node).collect())
+    }
+
+    /// Measures the parent distances of all nodes from 2 on, the ones of nodes 0 and 1 being fixed
+    /// by the `BoundaryPolicy`, and how often nodes are parents. The latter include all nodes.
+    pub fn edge_statistics(&self) -> Result<GraphStats> {
+        ensure!(self.nodes > 2, "the graph has no interior nodes");
+
+        let mut stats = GraphStats {
+            min_parent_distance: usize::MAX,
+            max_parent_distance: 0,
+            mean_parent_distance: 0.0,
+            children_histogram: Vec::new(),
+            fallback_parents: 0,
+        };
+        let mut total_distance = 0u64;
+        let mut parents = vec![0; self.base_degree];
+        for node in 2..self.nodes {
+            stats.fallback_parents += self.sample_parents(node, &mut parents);
+            for &parent in &parents {
+                let distance = node - parent as usize;
+                stats.min_parent_distance = min(stats.min_parent_distance, distance);
+                stats.max_parent_distance = max(stats.max_parent_distance, distance);
+                total_distance += distance as u64;
+            }
+        }
+        stats.mean_parent_distance =
+            total_distance as f64 / ((self.nodes - 2) * self.base_degree) as f64;
+
+        for children in reverse_adjacency(self)? {
+            if stats.children_histogram.len() <= children.len() {
+                stats.children_histogram.resize(children.len() + 1, 0);
+            }
+            stats.children_histogram[children.len()] += 1;
+        }
+
+        Ok(stats)
+    }
+
+    /// Rates the seed of this graph as the mean fraction of distinct parents of up to
+    /// `SEED_QUALITY_SAMPLES` interior nodes, evenly spread over the graph. A degenerate seed
+    /// repeats parents and scores lower.
+    ///
+    /// The first nodes have fewer distinct candidates than parents, so the quality is always
+    /// smaller than `1.0`.
+    pub fn seed_quality(&self) -> Result<f64> {
+        ensure!(self.nodes > 2, "the graph has no interior nodes");
+
+        let interior = self.nodes - 2;
+        let samples = min(interior, SEED_QUALITY_SAMPLES);
+        let mut parents = vec![0; self.degree()];
+        let mut total = 0.0;
+        for i in 0..samples {
+            let node = 2 + i * interior / samples;
+            self.parents(node, &mut parents)?;
+            parents.sort_unstable();
+            parents.dedup();
+            total += parents.len() as f64 / self.degree() as f64;
+            parents.resize(self.degree(), 0);
+        }
+
+        Ok(total / samples as f64)
+    }
+
+    /// Creates a graph from random seeds drawn from `OsRng`, returning the first one whose
+    /// `seed_quality` is at least `quality_threshold`. The chosen seed is available via
+    /// `Graph::seed`.
+    ///
+    /// Fails if none of `max_tries` seeds is good enough.
+    pub fn new_with_good_seed(
+        nodes: usize,
+        base_degree: usize,
+        api_version: ApiVersion,
+        quality_threshold: f64,
+        max_tries: usize,
+    ) -> Result<Self> {
+        for _ in 0..max_tries {
+            let graph = BucketGraph::from_seed(nodes, base_degree, new_seed(), api_version)?;
+            if graph.seed_quality()? >= quality_threshold {
+                return Ok(graph);
+            }
+        }
+
+        Err(format_err!(
+            "no seed of quality {} found in {} tries",
+            quality_threshold,
+            max_tries
+        ))
+    }
+}
+
+/// The edge statistics of a `BucketGraph`, see `BucketGraph::edge_statistics`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphStats {
+    pub min_parent_distance: usize,
+    pub max_parent_distance: usize,
+    pub mean_parent_distance: f64,
+    /// The number of nodes being the parent of exactly `i` distinct nodes, at index `i`.
+    pub children_histogram: Vec<usize>,
+    /// The number of sampled parents which were the node itself and got replaced by its
+    /// immediate predecessor.
+    pub fallback_parents: usize,
+}
+
+/// A single bucket sampled parent draw, see `BucketGraph::sampled_edges`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampledEdge {
+    /// The index of the sampled node in the metagraph, which has `degree - 1` nodes per node.
+    pub meta_index: u64,
+    /// The distance of the sampled metagraph node from the first metagraph node of the node.
+    pub back_dist: u64,
+    /// The sampled metagraph node mapped back onto the graph.
+    pub raw_parent: u32,
+    /// The parent used: `raw_parent`, or the immediate predecessor if `raw_parent` is the node
+    /// itself.
+    pub final_parent: u32,
+}
+
+/// Creates a `BucketGraph` from named parameters, as an alternative to the positional ones of
+/// `Graph::new` and `BucketGraph::from_seed`.
+///
+/// Only the number of nodes is required. The base degree defaults to `BASE_DEGREE`, the seed to
+/// a random one and the api version to `ApiVersion::V1_1_0`.
+#[derive(Debug, Clone, Copy)]
+pub struct BucketGraphBuilder {
+    nodes: Option<usize>,
+    base_degree: usize,
+    expansion_degree: usize,
+    seed: Option<[u8; 28]>,
+    api_version: ApiVersion,
+    boundary_policy: BoundaryPolicy,
+    seed_policy: SeedPolicy,
+}
+
+impl Default for BucketGraphBuilder {
+    fn default() -> Self {
+        BucketGraphBuilder {
+            nodes: None,
+            base_degree: BASE_DEGREE,
+            expansion_degree: 0,
+            seed: None,
+            api_version: ApiVersion::V1_1_0,
+            boundary_policy: BoundaryPolicy::default(),
+            seed_policy: SeedPolicy::default(),
+        }
+    }
+}
+
+impl BucketGraphBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn nodes(mut self, nodes: usize) -> Self {
+        self.nodes = Some(nodes);
+        self
+    }
+
+    pub fn base_degree(mut self, base_degree: usize) -> Self {
+        self.base_degree = base_degree;
+        self
+    }
+
+    /// Sets the expansion degree, which must be zero for a `BucketGraph`.
+    pub fn expansion_degree(mut self, expansion_degree: usize) -> Self {
+        self.expansion_degree = expansion_degree;
+        self
+    }
+
+    /// Sets an already derived DRG seed.
+    pub fn seed(mut self, seed: [u8; 28]) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Sets the seed derived from `porep_id`, like `Graph::new` does.
+    pub fn porep_id(self, porep_id: PoRepID) -> Self {
+        self.seed(derive_drg_seed(porep_id))
+    }
+
+    pub fn api_version(mut self, api_version: ApiVersion) -> Self {
+        self.api_version = api_version;
+        self
+    }
+
+    pub fn boundary_policy(mut self, boundary_policy: BoundaryPolicy) -> Self {
+        self.boundary_policy = boundary_policy;
+        self
+    }
+
+    pub fn seed_policy(mut self, seed_policy: SeedPolicy) -> Self {
+        self.seed_policy = seed_policy;
+        self
+    }
+
+    /// Creates the graph, validating the parameters like `BucketGraph::from_seed`.
+    pub fn build<H: Hasher>(self) -> Result<BucketGraph<H>> {
+        let nodes = self
+            .nodes
+            .ok_or_else(|| format_err!("the number of nodes is required"))?;
+        ensure!(self.expansion_degree == 0, "Expension degree must be zero.");
+        let seed = self.seed.unwrap_or_else(new_seed);
+
+        Ok(
+            BucketGraph::from_seed(nodes, self.base_degree, seed, self.api_version)?
+                .with_boundary_policy(self.boundary_policy)
+                .with_seed_policy(self.seed_policy),
+        )
+    }
+}
+
+impl<H: Hasher> ParameterSetMetadata for BucketGraph<H> {
+    fn identifier(&self) -> String {
+        // NOTE: Seed is not included because it does not influence parameter generation.
+        // The default policies are omitted to keep existing identifiers stable.
+        let boundary_policy = match self.boundary_policy {
+            BoundaryPolicy::Zeroed => String::new(),
+            policy => format!("; boundary: {:?}", policy),
+        };
+        let seed_policy = match self.seed_policy {
+            SeedPolicy::Shared => String::new(),
+            policy => format!("; seed: {:?}", policy),
+        };
+        format!(
+            "drgraph::BucketGraph{{size: {}; degree: {}; hasher: {}{}{}}}",
+            self.nodes,
+            self.degree(),
+            H::name(),
+            boundary_policy,
+            seed_policy,
+        )
+    }
+
+    fn sector_size(&self) -> u64 {
+        (self.nodes * NODE_SIZE) as u64
+    }
+}
+
+impl<H: Hasher> Graph<H> for BucketGraph<H> {
+    type Key = H::Domain;
+
+    fn create_key(
+        &self,
+        id: &H::Domain,
+        node: usize,
+        parents: &[u32],
+        base_parents_data: &[u8],
+        _exp_parents_data: Option<&[u8]>,
+    ) -> Result<Self::Key> {
+        let mut hasher = Sha256::new();
+        hasher.update(AsRef::<[u8]>::as_ref(id));
+
+        // The hash is about the parents, hence skip if a node doesn't have any parents
+        if node != parents[0] as usize {
+            for parent in parents.iter() {
+                let offset = data_at_node_offset(*parent as usize);
+                hasher.update(&base_parents_data[offset..offset + NODE_SIZE]);
+            }
+        }
+
+        let hash = hasher.finalize();
+        Ok(bytes_into_fr_repr_safe(hash.as_ref()).into())
+    }
+
+    #[inline]
+    fn parents(&self, node: usize, parents: &mut [u32]) -> Result<()> {
+        let m = self.degree();
+
+        match node {
+            // There are special cases for the first and second node, see `BoundaryPolicy`.
+            0 | 1 => {
+                let boundary_parent = match self.boundary_policy {
+                    BoundaryPolicy::Zeroed => 0,
+                    BoundaryPolicy::SelfReferenced => node as u32,
+                };
+                // Use the degree of the current graph (`m`) as `parents.len()` might be bigger than
+                // that (that's the case for Stacked Graph).
+                for parent in parents.iter_mut().take(m) {
+                    *parent = boundary_parent;
+                }
+                Ok(())
+            }
+            _ => {
+                self.sample_parents(node, parents);
+                Ok(())
+            }
+        }
+    }
+
+    #[inline]
+    fn size(&self) -> usize {
+        self.nodes
+    }
+
+    /// Returns the degree of the graph.
+    #[inline]
+    fn degree(&self) -> usize {
+        self.base_degree
+    }
+
+    fn seed(&self) -> [u8; 28] {
+        self.seed
+    }
+
+    fn new(
+        nodes: usize,
+        base_degree: usize,
+        expansion_degree: usize,
+        porep_id: PoRepID,
+        api_version: ApiVersion,
+    ) -> Result<Self> {
+        ensure!(expansion_degree == 0, "Expension degree must be zero.");
+
+        BucketGraph::from_seed(nodes, base_degree, derive_drg_seed(porep_id), api_version)
+    }
+}
+
+/// Creates one graph per seed, in order, all of them with the same number of nodes and base
+/// degree. This is the usual setup of the layers of a stacked construction.
+pub fn layered_graphs<H: Hasher>(
+    nodes: usize,
+    base_degree: usize,
+    seeds: &[[u8; 28]],
+    api_version: ApiVersion,
+) -> Result<Vec<BucketGraph<H>>> {
+    seeds
+        .iter()
+        .map(|seed| BucketGraph::from_seed(nodes, base_degree, *seed, api_version))
+        .collect()
+}
+
+/// Returns the smallest number of nodes of a `BucketGraph` with the given base degree, as
+/// accepted by `BucketGraph::from_seed`, or `None` if bucket sampling is undefined for this degree.
+///
+/// Nodes 0 and 1 are special cased, so node 2 is the first sampled node. Sampling node `i` draws
+/// from `ceil(log2(i * (base_degree - 1)))` buckets, which must not be zero, hence the base
+/// degree must be at least 2. Every node from 2 on is then well-defined, and the smallest graph
+/// holds nodes 0 and 1 only.
+pub fn min_nodes_for_degree(base_degree: usize) -> Option<usize> {
+    if base_degree < 2 {
+        None
+    } else {
+        Some(2)
+    }
+}
+
+pub fn derive_drg_seed(porep_id: PoRepID) -> [u8; 28] {
+    let mut drg_seed = [0; 28];
+    let raw_seed = derive_porep_domain_seed(DRSAMPLE_DST, porep_id);
+    drg_seed.copy_from_slice(&raw_seed[..28]);
+    drg_seed
+}
+
+/// Derives a DRG seed from arbitrary bytes, e.g. a replica id or a test vector name.
+///
+/// The seed is the first 28 bytes of the SHA256 digest of `input`, so identical input yields
+/// the identical seed on every platform.
+pub fn seed_from_bytes(input: &[u8]) -> [u8; 28] {
+    let mut seed = [0; 28];
+    seed.copy_from_slice(&Sha256::digest(input)[..28]);
+    seed
+}
+
+/// Draws a random DRG seed from `OsRng`.
+pub fn new_seed() -> [u8; 28] {
+    new_seed_from_rng(&mut OsRng)
+}
+
+/// Draws a DRG seed from `rng`, so that tests and benchmarks can reproduce a graph by passing a
+/// seeded RNG.
+pub fn new_seed_from_rng<R: RngCore>(rng: &mut R) -> [u8; 28] {
+    let mut seed = [0; 28];
+    rng.fill_bytes(&mut seed);
+    seed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use filecoin_hashers::{
+        blake2s::Blake2sHasher, poseidon::PoseidonHasher, sha256::Sha256Hasher,
+    };
+    use generic_array::typenum::{U0, U2, U4, U8};
+    use memmap::{MmapMut, MmapOptions};
+    use merkletree::store::StoreConfig;
+
+    use filecoin_hashers::Domain;
+
+    use crate::{
+        merkle::{create_base_merkle_tree, generate_tree, DiskStore, MerkleTreeWrapper},
+        test_helper::{assert_parents_pure, parents_throughput, test_data_for},
+    };
+
+    // Create and return an object of MmapMut backed by in-memory copy of data.
+    pub fn mmap_from(data: &[u8]) -> MmapMut {
+        let mut mm = MmapOptions::new()
+            .len(data.len())
+            .map_anon()
+            .expect("Failed to create memory map");
+        mm.copy_from_slice(data);
+        mm
+    }
+
+    fn graph_bucket<H: Hasher>() {
+        // These PoRepIDs do not correspond to the small-sized graphs used in
+        // the tests. However, they are sufficient to distinguish legacy vs new
+        // behavior of parent ordering.
+        let porep_id = |id: u8| {
+            let mut porep_id = [0u8; 32];
+            porep_id[0] = id;
+
+            porep_id
+        };
+
+        let legacy_porep_id = porep_id(0);
+        let new_porep_id = porep_id(5);
+
+        graph_bucket_aux::<H>(legacy_porep_id, ApiVersion::V1_0_0);
+        graph_bucket_aux::<H>(new_porep_id, ApiVersion::V1_1_0);
+    }
+
+    fn graph_bucket_aux<H: Hasher>(porep_id: PoRepID, api_version: ApiVersion) {
+        let degree = BASE_DEGREE;
+
+        for &size in &[4, 16, 256, 2048] {
+            let g = BucketGraph::<H>::new(size, degree, 0, porep_id, api_version)
+                .expect("bucket graph new failed");
+
+            assert_eq!(g.size(), size, "wrong nodes count");
+
+            let mut parents = vec![0; degree];
+            g.parents(0, &mut parents).expect("parents failed");
+            assert_eq!(parents, vec![0; degree as usize]);
+            parents = vec![0; degree];
+            g.parents(1, &mut parents).expect("parents failed");
+            assert_eq!(parents, vec![0; degree as usize]);
+
+            for i in 1..size {
+                let mut pa1 = vec![0; degree];
+                g.parents(i, &mut pa1).expect("parents failed");
+                let mut pa2 = vec![0; degree];
+                g.parents(i, &mut pa2).expect("parents failed");
+
+                assert_eq!(pa1.len(), degree);
+                assert_eq!(pa1, pa2, "different parents on the same node");
+                assert_parents_pure(&g, i, 16);
+
+                let mut p1 = vec![0; degree];
+                g.parents(i, &mut p1).expect("parents failed");
+                let mut p2 = vec![0; degree];
+                g.parents(i, &mut p2).expect("parents failed");
+
+                for parent in p1 {
+                    // TODO: fix me
+                    assert_ne!(i, parent as usize, "self reference found");
+                }
+
+                match api_version {
+                    ApiVersion::V1_0_0 => {
+                        assert_eq!(
+                            i - 1,
+                            pa1[degree - 1] as usize,
+                            "immediate predecessor was not last DRG parent"
+                        );
+                    }
                     ApiVersion::V1_1_0 => {
                         assert_eq!(
                             i - 1,
@@ -349,6 +1342,1029 @@ mod tests {
         }
     }
 
+    #[test]
+    fn graph_boundary_policy() {
+        let g = BucketGraph::<PoseidonHasher>::new(16, BASE_DEGREE, 0, [1; 32], ApiVersion::V1_1_0)
+            .expect("bucket graph new failed");
+        assert_eq!(g.boundary_policy(), BoundaryPolicy::Zeroed);
+
+        let self_referenced = g.with_boundary_policy(BoundaryPolicy::SelfReferenced);
+        assert_ne!(g, self_referenced);
+        assert_ne!(g.identifier(), self_referenced.identifier());
+        assert!(self_referenced
+            .identifier()
+            .contains("boundary: SelfReferenced"));
+
+        let parents_of = |g: &BucketGraph<PoseidonHasher>, node: usize| {
+            let mut parents = vec![0; BASE_DEGREE];
+            g.parents(node, &mut parents).expect("parents failed");
+            parents
+        };
+
+        assert_eq!(parents_of(&g, 0), vec![0; BASE_DEGREE]);
+        assert_eq!(parents_of(&g, 1), vec![0; BASE_DEGREE]);
+        assert_eq!(parents_of(&self_referenced, 0), vec![0; BASE_DEGREE]);
+        assert_eq!(parents_of(&self_referenced, 1), vec![1; BASE_DEGREE]);
+
+        // The policy does not affect any other node.
+        for node in 2..16 {
+            assert_eq!(parents_of(&g, node), parents_of(&self_referenced, node));
+        }
+    }
+
+    #[test]
+    fn graph_node_rng_seed() {
+        let g = BucketGraph::<PoseidonHasher>::new(64, BASE_DEGREE, 0, [1; 32], ApiVersion::V1_1_0)
+            .expect("bucket graph new failed");
+
+        for &node in &[2, 42, 63] {
+            let seed = g.node_rng_seed(node);
+            assert_eq!(seed[..28], g.seed());
+            assert_eq!(seed[28..], (node as u32).to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn graph_seed_policy() {
+        let graph = |degree: usize| {
+            BucketGraph::<PoseidonHasher>::new(64, degree, 0, [1; 32], ApiVersion::V1_1_0)
+                .expect("bucket graph new failed")
+        };
+        let (g6, g8) = (graph(6), graph(8));
+        assert_eq!(g6.seed_policy(), SeedPolicy::Shared);
+
+        let separated6 = g6.with_seed_policy(SeedPolicy::DegreeSeparated);
+        let separated8 = g8.with_seed_policy(SeedPolicy::DegreeSeparated);
+        assert_ne!(g6, separated6);
+        assert_ne!(g6.identifier(), separated6.identifier());
+        assert_eq!(separated6.seed(), g6.seed());
+        assert_eq!(separated6.with_seed_policy(SeedPolicy::Shared), g6);
+
+        let parents_of = |g: &BucketGraph<PoseidonHasher>, node: usize| {
+            let mut parents = vec![0; g.degree()];
+            g.parents(node, &mut parents).expect("parents failed");
+            parents
+        };
+
+        let mut changed = 0;
+        for node in 2..64 {
+            // By default, graphs of different degrees draw from the same rngs.
+            assert_eq!(g6.node_rng_seed(node), g8.node_rng_seed(node));
+            assert_ne!(
+                separated6.node_rng_seed(node),
+                separated8.node_rng_seed(node)
+            );
+            assert_ne!(separated6.node_rng_seed(node), g6.node_rng_seed(node));
+            assert_eq!(
+                separated6.node_rng_seed(node)[28..],
+                (node as u32).to_le_bytes()
+            );
+
+            // The predecessor is always a parent, the sampled ones change.
+            let parents = parents_of(&separated6, node);
+            assert_eq!(parents[0] as usize, node - 1);
+            if parents != parents_of(&g6, node) {
+                changed += 1;
+            }
+        }
+        assert!(changed > 0);
+
+        let built = BucketGraphBuilder::new()
+            .nodes(64)
+            .base_degree(6)
+            .porep_id([1; 32])
+            .seed_policy(SeedPolicy::DegreeSeparated)
+            .build::<PoseidonHasher>()
+            .expect("build failed");
+        assert_eq!(built, separated6);
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn graph_u32_boundary() {
+        let nodes = u32::MAX as usize + 1;
+        let g = BucketGraph::<PoseidonHasher>::from_seed(
+            nodes,
+            BASE_DEGREE,
+            [3; 28],
+            ApiVersion::V1_1_0,
+        )
+        .expect("from_seed failed");
+
+        let mut parents = vec![0; BASE_DEGREE];
+        for &node in &[nodes - 1, nodes - 2, nodes / 2] {
+            g.parents(node, &mut parents).expect("parents failed");
+            assert_eq!(parents[0] as usize, node - 1);
+            assert!(parents.iter().all(|&parent| (parent as usize) < node));
+        }
+
+        assert!(BucketGraph::<PoseidonHasher>::from_seed(
+            nodes + 1,
+            BASE_DEGREE,
+            [3; 28],
+            ApiVersion::V1_1_0
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn graph_sampled_edges() {
+        let g =
+            BucketGraph::<PoseidonHasher>::new(256, BASE_DEGREE, 0, [2; 32], ApiVersion::V1_1_0)
+                .expect("bucket graph new failed");
+        let m_prime = BASE_DEGREE as u64 - 1;
+
+        let mut parents = vec![0; BASE_DEGREE];
+        for node in 2..256 {
+            let edges = g.sampled_edges(node).expect("sampled_edges failed");
+            assert_eq!(edges, g.sampled_edges(node).expect("sampled_edges failed"));
+            assert_eq!(edges.len(), BASE_DEGREE - 1);
+
+            g.parents(node, &mut parents).expect("parents failed");
+            for (edge, &parent) in edges.iter().zip(parents[1..].iter()) {
+                assert_eq!(edge.final_parent, parent);
+                assert_eq!(edge.meta_index + edge.back_dist, node as u64 * m_prime);
+                assert_eq!(edge.raw_parent as u64, edge.meta_index / m_prime);
+                if edge.raw_parent != edge.final_parent {
+                    assert_eq!(edge.raw_parent as usize, node);
+                    assert_eq!(edge.final_parent as usize, node - 1);
+                }
+            }
+        }
+
+        assert!(g.sampled_edges(1).is_err());
+        assert!(g.sampled_edges(256).is_err());
+    }
+
+    #[test]
+    fn graph_new_with_good_seed() {
+        let g = BucketGraph::<PoseidonHasher>::new_with_good_seed(
+            1024,
+            BASE_DEGREE,
+            ApiVersion::V1_1_0,
+            0.5,
+            10,
+        )
+        .expect("no good seed found");
+        let quality = g.seed_quality().expect("seed_quality failed");
+        assert!(quality >= 0.5);
+        assert!(quality < 1.0);
+
+        // The seed reproduces the graph.
+        let rebuilt = BucketGraph::<PoseidonHasher>::from_seed(
+            1024,
+            BASE_DEGREE,
+            g.seed(),
+            ApiVersion::V1_1_0,
+        )
+        .expect("from_seed failed");
+        assert_eq!(rebuilt, g);
+
+        assert!(BucketGraph::<PoseidonHasher>::new_with_good_seed(
+            1024,
+            BASE_DEGREE,
+            ApiVersion::V1_1_0,
+            1.0,
+            3
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn graph_edge_statistics() {
+        let nodes = 1000;
+        let new = |porep_id| {
+            BucketGraph::<PoseidonHasher>::new(nodes, BASE_DEGREE, 0, porep_id, ApiVersion::V1_1_0)
+                .expect("bucket graph new failed")
+        };
+        let g = new([5; 32]);
+        let stats = g.edge_statistics().expect("edge_statistics failed");
+        assert_eq!(
+            stats,
+            new([5; 32])
+                .edge_statistics()
+                .expect("edge_statistics failed")
+        );
+        assert_ne!(
+            stats,
+            new([6; 32])
+                .edge_statistics()
+                .expect("edge_statistics failed")
+        );
+
+        // The immediate predecessor is always a parent.
+        assert_eq!(stats.min_parent_distance, 1);
+        assert!(stats.max_parent_distance < nodes);
+        assert!(stats.mean_parent_distance > 1.0);
+        assert!(stats.mean_parent_distance < stats.max_parent_distance as f64);
+        assert!(stats.fallback_parents <= (nodes - 2) * (BASE_DEGREE - 1));
+
+        // Every node is counted once, and every distinct edge once.
+        let edges: usize = reverse_adjacency(&g)
+            .expect("reverse_adjacency failed")
+            .iter()
+            .map(Vec::len)
+            .sum();
+        assert_eq!(stats.children_histogram.iter().sum::<usize>(), nodes);
+        assert_eq!(
+            stats
+                .children_histogram
+                .iter()
+                .enumerate()
+                .map(|(children, count)| children * count)
+                .sum::<usize>(),
+            edges
+        );
+
+        assert!(
+            BucketGraph::<PoseidonHasher>::new(2, BASE_DEGREE, 0, [5; 32], ApiVersion::V1_1_0)
+                .expect("bucket graph new failed")
+                .edge_statistics()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn graph_builder() {
+        let porep_id = [3; 32];
+        let built = BucketGraphBuilder::new()
+            .nodes(128)
+            .porep_id(porep_id)
+            .build::<PoseidonHasher>()
+            .expect("build failed");
+        assert_eq!(
+            built,
+            BucketGraph::new(128, BASE_DEGREE, 0, porep_id, ApiVersion::V1_1_0)
+                .expect("bucket graph new failed")
+        );
+
+        let seed = [8; 28];
+        let built = BucketGraphBuilder::new()
+            .nodes(64)
+            .base_degree(4)
+            .expansion_degree(0)
+            .seed(seed)
+            .api_version(ApiVersion::V1_0_0)
+            .boundary_policy(BoundaryPolicy::SelfReferenced)
+            .build::<PoseidonHasher>()
+            .expect("build failed");
+        assert_eq!(
+            built,
+            BucketGraph::from_seed(64, 4, seed, ApiVersion::V1_0_0)
+                .expect("from_seed failed")
+                .with_boundary_policy(BoundaryPolicy::SelfReferenced)
+        );
+
+        // Without a seed, a random one is used.
+        let random = BucketGraphBuilder::new().nodes(64);
+        assert_ne!(
+            random
+                .build::<PoseidonHasher>()
+                .expect("build failed")
+                .seed(),
+            random
+                .build::<PoseidonHasher>()
+                .expect("build failed")
+                .seed()
+        );
+
+        for builder in &[
+            BucketGraphBuilder::new(),
+            BucketGraphBuilder::new().nodes(64).expansion_degree(8),
+            BucketGraphBuilder::new().nodes(64).base_degree(1),
+            BucketGraphBuilder::new().nodes(1),
+        ] {
+            assert!(builder.build::<PoseidonHasher>().is_err());
+        }
+    }
+
+    #[test]
+    fn graph_min_nodes_for_degree() {
+        assert_eq!(min_nodes_for_degree(0), None);
+        assert_eq!(min_nodes_for_degree(1), None);
+
+        for &degree in &[2, 3, BASE_DEGREE, 13] {
+            let nodes = min_nodes_for_degree(degree).expect("degree is valid");
+            let g =
+                BucketGraph::<PoseidonHasher>::new(nodes, degree, 0, [1; 32], ApiVersion::V1_1_0)
+                    .expect("bucket graph new failed");
+
+            let mut parents = vec![0; degree];
+            g.parents(nodes - 1, &mut parents).expect("parents failed");
+            assert!(parents.iter().all(|&p| (p as usize) < nodes - 1));
+
+            // The first sampled node is well-defined too.
+            let g = BucketGraph::<PoseidonHasher>::new(
+                nodes + 1,
+                degree,
+                0,
+                [1; 32],
+                ApiVersion::V1_1_0,
+            )
+            .expect("bucket graph new failed");
+            g.parents(nodes, &mut parents).expect("parents failed");
+            assert!(parents.iter().all(|&p| (p as usize) < nodes));
+
+            // `from_seed` accepts exactly the graphs of at least `min_nodes_for_degree` nodes.
+            assert!(BucketGraph::<PoseidonHasher>::from_seed(
+                nodes,
+                degree,
+                [1; 28],
+                ApiVersion::V1_1_0
+            )
+            .is_ok());
+            assert!(BucketGraph::<PoseidonHasher>::from_seed(
+                nodes - 1,
+                degree,
+                [1; 28],
+                ApiVersion::V1_1_0
+            )
+            .is_err());
+        }
+
+        for &degree in &[0, 1] {
+            assert!(
+                BucketGraph::<PoseidonHasher>::new(16, degree, 0, [1; 32], ApiVersion::V1_1_0)
+                    .is_err()
+            );
+        }
+
+        for &nodes in &[0, 1] {
+            assert!(BucketGraph::<PoseidonHasher>::new(
+                nodes,
+                BASE_DEGREE,
+                0,
+                [1; 32],
+                ApiVersion::V1_1_0
+            )
+            .is_err());
+        }
+        assert!(
+            BucketGraph::<PoseidonHasher>::new(2, BASE_DEGREE, 0, [1; 32], ApiVersion::V1_1_0)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn graph_children() {
+        let nodes = 64;
+        let g =
+            BucketGraph::<PoseidonHasher>::new(nodes, BASE_DEGREE, 0, [9; 32], ApiVersion::V1_1_0)
+                .expect("bucket graph new failed");
+        let index = g.build_child_index().expect("build_child_index failed");
+        assert_eq!(index.len(), nodes);
+
+        for node in 0..nodes {
+            let children = g.children(node).expect("children failed");
+            assert_eq!(children, index[node], "wrong children of node {}", node);
+            assert!(children.windows(2).all(|w| w[0] < w[1]));
+
+            for &child in &children {
+                let mut parents = vec![0; BASE_DEGREE];
+                g.parents(child, &mut parents).expect("parents failed");
+                assert!(parents.contains(&(node as u32)));
+            }
+        }
+
+        // Every parent of a node lists it as a child.
+        let mut parents = vec![0; BASE_DEGREE];
+        for node in 1..nodes {
+            g.parents(node, &mut parents).expect("parents failed");
+            for &parent in &parents {
+                assert!(index[parent as usize].contains(&node));
+            }
+        }
+
+        assert!(g.children(nodes).is_err());
+    }
+
+    #[test]
+    fn graph_descendants() {
+        let nodes = 32;
+        let g =
+            BucketGraph::<PoseidonHasher>::new(nodes, BASE_DEGREE, 0, [9; 32], ApiVersion::V1_1_0)
+                .expect("bucket graph new failed");
+
+        let all_parents = (0..nodes)
+            .map(|node| {
+                let mut parents = vec![0; BASE_DEGREE];
+                g.parents(node, &mut parents).expect("parents failed");
+                parents
+            })
+            .collect::<Vec<_>>();
+
+        for node in 0..nodes {
+            // Parents always precede their children, so reachability can be computed in a
+            // single forward pass.
+            let mut reached = vec![false; nodes];
+            for child in (node + 1)..nodes {
+                reached[child] = all_parents[child]
+                    .iter()
+                    .any(|&p| p as usize == node || reached[p as usize]);
+            }
+            let expected = (0..nodes)
+                .filter(|&child| reached[child])
+                .collect::<BTreeSet<_>>();
+
+            assert_eq!(
+                g.descendants(node).expect("descendants failed"),
+                expected,
+                "wrong descendants of node {}",
+                node
+            );
+        }
+        assert!(g.descendants(nodes).is_err());
+    }
+
+    #[test]
+    fn graph_parents_merkle_tree() {
+        let mut rng = rand::thread_rng();
+        let nodes = 64;
+        let g =
+            BucketGraph::<PoseidonHasher>::new(nodes, BASE_DEGREE, 0, [3; 32], ApiVersion::V1_1_0)
+                .expect("bucket graph new failed");
+        let leaves: Vec<<PoseidonHasher as Hasher>::Domain> = (0..nodes)
+            .map(|_| <PoseidonHasher as Hasher>::Domain::random(&mut rng))
+            .collect();
+
+        let node = 42;
+        let mut parents = vec![0; BASE_DEGREE];
+        g.parents(node, &mut parents).expect("parents failed");
+        parents.sort_unstable();
+
+        let tree = g
+            .parents_merkle_tree(node, &leaves)
+            .expect("parents_merkle_tree failed");
+        let root = g
+            .parents_merkle_root(node, &leaves)
+            .expect("parents_merkle_root failed");
+        assert_eq!(tree.root(), root);
+        assert_eq!(tree.leaves(), BASE_DEGREE.next_power_of_two());
+
+        for (i, parent) in parents.iter().enumerate() {
+            let proof = tree.gen_proof(i).expect("gen_proof failure");
+            assert!(proof.validate(i));
+            assert_eq!(proof.leaf(), leaves[*parent as usize]);
+            assert_eq!(proof.root(), root);
+        }
+
+        assert!(g.parents_merkle_root(nodes, &leaves).is_err());
+        assert!(g.parents_merkle_root(node, &leaves[1..]).is_err());
+    }
+
+    #[test]
+    fn graph_test_data_for() {
+        let g = BucketGraph::<PoseidonHasher>::new(64, BASE_DEGREE, 0, [3; 32], ApiVersion::V1_1_0)
+            .expect("bucket graph new failed");
+
+        let data = test_data_for(&g, NODE_SIZE, 7);
+        assert_eq!(data.len(), g.expected_size());
+        assert_eq!(data, test_data_for(&g, NODE_SIZE, 7));
+        assert_ne!(data, test_data_for(&g, NODE_SIZE, 8));
+        assert_eq!(test_data_for(&g, 16, 7).len(), g.size() * 16);
+
+        // Every node is a valid field element.
+        assert!(
+            create_base_merkle_tree::<BinaryMerkleTree<PoseidonHasher>>(None, 64, &data).is_ok()
+        );
+    }
+
+    #[test]
+    fn graph_verify_inclusion() {
+        let mut rng = rand::thread_rng();
+        let nodes = 64;
+        let g =
+            BucketGraph::<PoseidonHasher>::new(nodes, BASE_DEGREE, 0, [3; 32], ApiVersion::V1_1_0)
+                .expect("bucket graph new failed");
+        let data = test_data_for(&g, NODE_SIZE, 1);
+        let root = create_base_merkle_tree::<BinaryMerkleTree<PoseidonHasher>>(None, nodes, &data)
+            .expect("failed to create tree")
+            .root();
+
+        for &index in &[0, 17, nodes - 1] {
+            assert!(g
+                .verify_inclusion(&data, NODE_SIZE, index, &root)
+                .expect("verify_inclusion failed"));
+        }
+
+        let other_root = <PoseidonHasher as Hasher>::Domain::random(&mut rng);
+        assert!(!g
+            .verify_inclusion(&data, NODE_SIZE, 17, &other_root)
+            .expect("verify_inclusion failed"));
+
+        assert!(g.verify_inclusion(&data, NODE_SIZE, nodes, &root).is_err());
+        assert!(g
+            .verify_inclusion(&data[NODE_SIZE..], NODE_SIZE, 17, &root)
+            .is_err());
+        assert!(g.verify_inclusion(&data, 64, 17, &root).is_err());
+
+        // Nodes smaller than a field element are padded.
+        let packed = test_data_for(&g, 31, 2);
+        let packed_root =
+            create_base_merkle_tree_with_node_size::<BinaryMerkleTree<PoseidonHasher>>(
+                None, &packed, 31,
+            )
+            .expect("failed to create tree")
+            .root();
+        assert!(g
+            .verify_inclusion(&packed, 31, 17, &packed_root)
+            .expect("verify_inclusion failed"));
+        assert!(!g
+            .verify_inclusion(&packed, 31, 17, &root)
+            .expect("verify_inclusion failed"));
+    }
+
+    #[test]
+    fn graph_node_and_parents_proof() {
+        let mut rng = rand::thread_rng();
+        let nodes = 64;
+        let g =
+            BucketGraph::<PoseidonHasher>::new(nodes, BASE_DEGREE, 0, [3; 32], ApiVersion::V1_1_0)
+                .expect("bucket graph new failed");
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, nodes, None);
+        let root = tree.root();
+
+        let node = 42;
+        let proof = g
+            .gen_node_and_parents_proof(&tree, node)
+            .expect("gen_node_and_parents_proof failed");
+        assert!(proof.validate(&g, node, &root));
+        assert!(!proof.validate(&g, node + 1, &root));
+        assert!(!proof.validate(
+            &g,
+            node,
+            &<PoseidonHasher as Hasher>::Domain::random(&mut rng)
+        ));
+
+        // Drop the proof of the last parent.
+        let mut parents = vec![0; BASE_DEGREE];
+        g.parents(node, &mut parents).expect("parents failed");
+        let proofs = std::iter::once(node)
+            .chain(parents[..BASE_DEGREE - 1].iter().map(|&p| p as usize))
+            .map(|i| (tree.gen_proof(i).expect("gen_proof failure"), i))
+            .collect::<Vec<_>>();
+        let missing = NodeParentsProof {
+            proofs: coalesce(&proofs).expect("coalesce failure"),
+        };
+        assert!(!missing.validate(&g, node, &root));
+    }
+
+    #[test]
+    fn graph_parent_table() {
+        let nodes = 500;
+        let g =
+            BucketGraph::<PoseidonHasher>::new(nodes, BASE_DEGREE, 0, [4; 32], ApiVersion::V1_1_0)
+                .expect("bucket graph new failed");
+        let table = g.parent_table().expect("parent_table failed");
+        assert_eq!(table.len(), nodes);
+        assert_eq!(table.degree(), BASE_DEGREE);
+
+        let mut parents = vec![0; BASE_DEGREE];
+        for node in 0..nodes {
+            g.parents(node, &mut parents).expect("parents failed");
+            assert_eq!(table.parents(node), &parents[..]);
+        }
+    }
+
+    #[test]
+    fn graph_reverse() {
+        let nodes = 200;
+        let g =
+            BucketGraph::<PoseidonHasher>::new(nodes, BASE_DEGREE, 0, [6; 32], ApiVersion::V1_1_0)
+                .expect("bucket graph new failed");
+        let reversed = ReverseGraph::new(g);
+        assert_eq!(reversed.size(), nodes);
+        assert_eq!(reversed.degree(), BASE_DEGREE);
+        assert_eq!(reversed.seed(), g.seed());
+        assert_eq!(
+            reversed.merkle_tree_depth::<U2>(),
+            g.merkle_tree_depth::<U2>()
+        );
+
+        let mut parents = vec![0; BASE_DEGREE];
+        let mut reversed_parents = vec![0; BASE_DEGREE];
+        for node in 0..nodes {
+            reversed
+                .parents(node, &mut reversed_parents)
+                .expect("reversed parents failed");
+            assert!(reversed_parents.iter().all(|&p| p as usize >= node));
+
+            // Mirroring the reversed parents back yields the original ones.
+            g.parents(nodes - 1 - node, &mut parents)
+                .expect("parents failed");
+            let mirrored = reversed_parents
+                .iter()
+                .map(|&p| (nodes - 1) as u32 - p)
+                .collect::<Vec<_>>();
+            assert_eq!(mirrored, parents);
+        }
+
+        assert!(reversed.parents(nodes, &mut reversed_parents).is_err());
+        assert_eq!(reversed.into_inner(), g);
+    }
+
+    #[test]
+    fn graph_adjacency_delta() {
+        let nodes = 2000;
+        let g =
+            BucketGraph::<PoseidonHasher>::new(nodes, BASE_DEGREE, 0, [7; 32], ApiVersion::V1_1_0)
+                .expect("bucket graph new failed");
+
+        let mut encoded = Vec::new();
+        g.write_adjacency_delta(&mut encoded)
+            .expect("write_adjacency_delta failed");
+        let adjacency =
+            read_adjacency_delta(&mut encoded.as_slice()).expect("read_adjacency_delta failed");
+
+        assert_eq!(adjacency.len(), nodes);
+        for (node, decoded) in adjacency.iter().enumerate() {
+            let mut parents = vec![0; BASE_DEGREE];
+            g.parents(node, &mut parents).expect("parents failed");
+            parents.sort_unstable();
+            assert_eq!(decoded, &parents, "wrong parents of node {}", node);
+        }
+
+        // Fixed width encoding of the same adjacency, with the same header.
+        let fixed_width = 12 + nodes * BASE_DEGREE * 4;
+        assert!(
+            encoded.len() < fixed_width,
+            "delta encoding is {} bytes, fixed width is {} bytes",
+            encoded.len(),
+            fixed_width
+        );
+
+        assert!(read_adjacency_delta(&mut &encoded[..encoded.len() - 1]).is_err());
+
+        // A header claiming a huge adjacency fails on the missing parents instead of allocating.
+        let mut huge = u64::MAX.to_le_bytes().to_vec();
+        huge.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(read_adjacency_delta(&mut huge.as_slice()).is_err());
+        huge.extend_from_slice(&[0; 16]);
+        assert!(read_adjacency_delta(&mut huge.as_slice()).is_err());
+    }
+
+    #[test]
+    fn graph_varint() {
+        for &value in &[0, 1, 0x7f, 0x80, 0x3fff, 0x4000, u32::MAX - 1, u32::MAX] {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, value);
+            assert!(buf.len() <= 5);
+            assert_eq!(
+                read_varint(&mut buf.as_slice()).expect("read_varint failed"),
+                value
+            );
+        }
+
+        // The fifth byte may only hold the 4 most significant bits of a `u32`.
+        assert_eq!(
+            read_varint(&mut &[0xff, 0xff, 0xff, 0xff, 0x0f][..]).expect("read_varint failed"),
+            u32::MAX
+        );
+        assert!(read_varint(&mut &[0xff, 0xff, 0xff, 0xff, 0x10][..]).is_err());
+        assert!(read_varint(&mut &[0x80, 0x80, 0x80, 0x80, 0x7f][..]).is_err());
+        assert!(read_varint(&mut &[0xff, 0xff, 0xff, 0xff, 0xff, 0x00][..]).is_err());
+        assert!(read_varint(&mut &[0x80][..]).is_err());
+    }
+
+    /// A graph where every node only references its immediate predecessor.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct ChainGraph {
+        nodes: usize,
+    }
+
+    impl<H: Hasher> Graph<H> for ChainGraph {
+        type Key = ();
+
+        fn parents(&self, node: usize, parents: &mut [u32]) -> Result<()> {
+            for parent in parents.iter_mut() {
+                *parent = node.saturating_sub(1) as u32;
+            }
+            Ok(())
+        }
+
+        fn size(&self) -> usize {
+            self.nodes
+        }
+
+        fn degree(&self) -> usize {
+            BASE_DEGREE
+        }
+
+        fn new(
+            nodes: usize,
+            _base_degree: usize,
+            _expansion_degree: usize,
+            _porep_id: PoRepID,
+            _api_version: ApiVersion,
+        ) -> Result<Self> {
+            Ok(ChainGraph { nodes })
+        }
+
+        fn seed(&self) -> [u8; 28] {
+            [1; 28]
+        }
+
+        fn create_key(
+            &self,
+            _id: &H::Domain,
+            _node: usize,
+            _parents: &[u32],
+            _parents_data: &[u8],
+            _exp_parents_data: Option<&[u8]>,
+        ) -> Result<Self::Key> {
+            Ok(())
+        }
+    }
+
+    /// A `ChainGraph` with a single wrong parent, the first one of `bad_node`.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct FaultyGraph {
+        nodes: usize,
+        bad_node: usize,
+        bad_parent: u32,
+    }
+
+    impl<H: Hasher> Graph<H> for FaultyGraph {
+        type Key = ();
+
+        fn parents(&self, node: usize, parents: &mut [u32]) -> Result<()> {
+            Graph::<H>::parents(&ChainGraph { nodes: self.nodes }, node, parents)?;
+            if node == self.bad_node {
+                parents[0] = self.bad_parent;
+            }
+            Ok(())
+        }
+
+        fn size(&self) -> usize {
+            self.nodes
+        }
+
+        fn degree(&self) -> usize {
+            BASE_DEGREE
+        }
+
+        fn new(
+            nodes: usize,
+            _base_degree: usize,
+            _expansion_degree: usize,
+            _porep_id: PoRepID,
+            _api_version: ApiVersion,
+        ) -> Result<Self> {
+            Ok(FaultyGraph {
+                nodes,
+                bad_node: 0,
+                bad_parent: 0,
+            })
+        }
+
+        fn seed(&self) -> [u8; 28] {
+            [1; 28]
+        }
+
+        fn create_key(
+            &self,
+            _id: &H::Domain,
+            _node: usize,
+            _parents: &[u32],
+            _parents_data: &[u8],
+            _exp_parents_data: Option<&[u8]>,
+        ) -> Result<Self::Key> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn graph_verify_parents() {
+        for &boundary_policy in &[BoundaryPolicy::Zeroed, BoundaryPolicy::SelfReferenced] {
+            for &api_version in &[ApiVersion::V1_0_0, ApiVersion::V1_1_0] {
+                let g =
+                    BucketGraph::<PoseidonHasher>::new(300, BASE_DEGREE, 0, [2; 32], api_version)
+                        .expect("bucket graph new failed")
+                        .with_boundary_policy(boundary_policy);
+                g.verify_parents()
+                    .expect("bucket graph parents are malformed");
+            }
+        }
+        Graph::<PoseidonHasher>::verify_parents(&ChainGraph { nodes: 20 })
+            .expect("chain graph parents are malformed");
+
+        // Out of range, self referencing among other parents, and following the node.
+        for &bad_parent in &[20, 7, 8] {
+            let g = FaultyGraph {
+                nodes: 20,
+                bad_node: 7,
+                bad_parent,
+            };
+            let err = Graph::<PoseidonHasher>::verify_parents(&g)
+                .expect_err("malformed parents were accepted");
+            assert!(err.to_string().starts_with("node 7 "));
+        }
+    }
+
+    #[test]
+    fn graph_adjacency_equal() {
+        let new = |nodes: usize, porep_id: PoRepID| {
+            BucketGraph::<PoseidonHasher>::new(nodes, BASE_DEGREE, 0, porep_id, ApiVersion::V1_1_0)
+                .expect("bucket graph new failed")
+        };
+
+        let g = new(256, [1; 32]);
+        assert!(g
+            .adjacency_equal(&new(256, [1; 32]))
+            .expect("adjacency_equal failed"));
+        assert!(!g
+            .adjacency_equal(&new(256, [2; 32]))
+            .expect("adjacency_equal failed"));
+        assert!(!g
+            .adjacency_equal(&new(128, [1; 32]))
+            .expect("adjacency_equal failed"));
+        assert!(!g
+            .adjacency_equal(&g.with_boundary_policy(BoundaryPolicy::SelfReferenced))
+            .expect("adjacency_equal failed"));
+    }
+
+    #[test]
+    fn graph_density() {
+        // A chain of n nodes has n - 1 distinct edges out of n * (n - 1).
+        let chain = ChainGraph { nodes: 10 };
+        let density = Graph::<PoseidonHasher>::density(&chain).expect("density failed");
+        assert!((density - 0.1).abs() < 1e-12, "wrong density {}", density);
+
+        let g = BucketGraph::<PoseidonHasher>::new(32, BASE_DEGREE, 0, [1; 32], ApiVersion::V1_1_0)
+            .expect("bucket graph new failed");
+        let mut edges = BTreeSet::new();
+        for node in 0..32 {
+            let mut parents = vec![0; BASE_DEGREE];
+            g.parents(node, &mut parents).expect("parents failed");
+            edges.extend(
+                parents
+                    .into_iter()
+                    .filter(|&parent| parent as usize != node)
+                    .map(|parent| (parent, node)),
+            );
+        }
+        let density = g.density().expect("density failed");
+        assert!((density - edges.len() as f64 / (32.0 * 31.0)).abs() < 1e-12);
+
+        assert!(Graph::<PoseidonHasher>::density(&ChainGraph { nodes: 1 }).is_err());
+    }
+
+    #[test]
+    fn graph_pebbling_lower_bound_estimate() {
+        let nodes = 1024;
+        let samples = 8;
+        let chain = ChainGraph { nodes };
+        let g =
+            BucketGraph::<PoseidonHasher>::new(nodes, BASE_DEGREE, 0, [1; 32], ApiVersion::V1_1_0)
+                .expect("bucket graph new failed");
+
+        let chain_estimate =
+            Graph::<PoseidonHasher>::pebbling_lower_bound_estimate(&chain, samples)
+                .expect("pebbling_lower_bound_estimate failed");
+        let estimate = g
+            .pebbling_lower_bound_estimate(samples)
+            .expect("pebbling_lower_bound_estimate failed");
+
+        assert!(
+            chain_estimate < estimate,
+            "chain estimate {} is not lower than {}",
+            chain_estimate,
+            estimate
+        );
+        assert_eq!(
+            g.pebbling_lower_bound_estimate(samples)
+                .expect("pebbling_lower_bound_estimate failed")
+                .to_bits(),
+            estimate.to_bits()
+        );
+        assert!(g.pebbling_lower_bound_estimate(0).is_err());
+    }
+
+    fn depth_cached_graph<U: 'static + PoseidonArity>() {
+        for &nodes in &[64, 512, 4096] {
+            let g = BucketGraph::<PoseidonHasher>::new(
+                nodes,
+                BASE_DEGREE,
+                0,
+                [1; 32],
+                ApiVersion::V1_1_0,
+            )
+            .expect("bucket graph new failed");
+            let cached = DepthCachedGraph::<_, _, U>::new(g);
+
+            assert_eq!(cached.merkle_tree_depth(), g.merkle_tree_depth::<U>());
+            assert_eq!(cached.size(), nodes);
+            assert_eq!(cached.into_inner(), g);
+        }
+    }
+
+    #[test]
+    fn graph_depth_cached() {
+        depth_cached_graph::<U2>();
+        depth_cached_graph::<U8>();
+    }
+
+    #[test]
+    fn graph_layered() {
+        let nodes = 64;
+        let seeds = [[1u8; 28], [2u8; 28], [3u8; 28]];
+        let graphs =
+            layered_graphs::<PoseidonHasher>(nodes, BASE_DEGREE, &seeds, ApiVersion::V1_1_0)
+                .expect("layered_graphs failed");
+        assert_eq!(graphs.len(), seeds.len());
+
+        let adjacency = |g: &BucketGraph<PoseidonHasher>| {
+            (0..nodes)
+                .map(|node| {
+                    let mut parents = vec![0; BASE_DEGREE];
+                    g.parents(node, &mut parents).expect("parents failed");
+                    parents
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for (graph, seed) in graphs.iter().zip(seeds.iter()) {
+            let expected = BucketGraph::<PoseidonHasher>::from_seed(
+                nodes,
+                BASE_DEGREE,
+                *seed,
+                ApiVersion::V1_1_0,
+            )
+            .expect("from_seed failed");
+            assert_eq!(graph, &expected);
+            assert_eq!(graph.seed(), *seed);
+            assert_eq!(graph.size(), nodes);
+            assert_eq!(graph.degree(), BASE_DEGREE);
+        }
+
+        assert_ne!(adjacency(&graphs[0]), adjacency(&graphs[1]));
+        assert_ne!(adjacency(&graphs[1]), adjacency(&graphs[2]));
+
+        // `new` is `from_seed` with the seed derived from the porep id.
+        let porep_id = [5; 32];
+        assert_eq!(
+            BucketGraph::<PoseidonHasher>::new(nodes, BASE_DEGREE, 0, porep_id, ApiVersion::V1_1_0)
+                .expect("bucket graph new failed"),
+            BucketGraph::from_seed(
+                nodes,
+                BASE_DEGREE,
+                derive_drg_seed(porep_id),
+                ApiVersion::V1_1_0
+            )
+            .expect("from_seed failed")
+        );
+    }
+
+    #[test]
+    fn graph_seed_from_bytes() {
+        // Pinned so that changes to the derivation are caught.
+        assert_eq!(
+            seed_from_bytes(b"rust-fil-proofs"),
+            [
+                248, 206, 107, 88, 199, 106, 105, 81, 12, 187, 91, 197, 61, 135, 54, 59, 51, 111,
+                93, 156, 33, 18, 0, 36, 6, 32, 125, 214
+            ]
+        );
+        assert_eq!(seed_from_bytes(b"replica"), seed_from_bytes(b"replica"));
+        assert_ne!(seed_from_bytes(b"replica"), seed_from_bytes(b"replica2"));
+    }
+
+    #[test]
+    fn graph_new_seed_from_rng() {
+        let mut rng1 = ChaCha8Rng::from_seed([7; 32]);
+        let mut rng2 = ChaCha8Rng::from_seed([7; 32]);
+
+        let seed = new_seed_from_rng(&mut rng1);
+        assert_eq!(seed, new_seed_from_rng(&mut rng2));
+        assert_ne!(seed, new_seed_from_rng(&mut rng1));
+
+        let g1 =
+            BucketGraph::<PoseidonHasher>::from_seed(64, BASE_DEGREE, seed, ApiVersion::V1_1_0)
+                .expect("from_seed failure");
+        let g2 =
+            BucketGraph::<PoseidonHasher>::from_seed(64, BASE_DEGREE, seed, ApiVersion::V1_1_0)
+                .expect("from_seed failure");
+        assert_eq!(g1, g2);
+    }
+
+    #[test]
+    fn graph_parents_throughput() {
+        let g = BucketGraph::<PoseidonHasher>::new(
+            1 << 16,
+            BASE_DEGREE,
+            0,
+            [1; 32],
+            ApiVersion::V1_1_0,
+        )
+        .expect("bucket graph new failed");
+
+        let throughput = parents_throughput(&g, 1000).expect("parents_throughput failed");
+        assert!(throughput.is_finite());
+        assert!(throughput > 0.0);
+
+        assert!(parents_throughput(&g, 0).is_err());
+    }
+
     #[test]
     fn graph_bucket_sha256() {
         graph_bucket::<Sha256Hasher>();
@@ -364,7 +2380,7 @@ mod tests {
         let porep_id = [1; 32];
         let g = BucketGraph::<H>::new(leafs, BASE_DEGREE, 0, porep_id, ApiVersion::V1_1_0)
             .expect("bucket graph new failed");
-        let data = vec![2u8; NODE_SIZE * leafs];
+        let data = test_data_for(&g, NODE_SIZE, 2);
 
         let mmapped = &mmap_from(&data);
         let tree =
@@ -0,0 +1,87 @@
+//! Vetted parameter sets for `BucketGraph`s.
+
+use filecoin_hashers::Hasher;
+
+use crate::{
+    api_version::ApiVersion,
+    drgraph::{BucketGraph, Graph, BASE_DEGREE},
+    error::Result,
+    PoRepID,
+};
+
+/// Named graph configurations, matching the sector sizes used in production.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityLevel {
+    /// A 2KiB sector, only suitable for tests.
+    Test,
+    /// A 32GiB sector.
+    Standard,
+    /// A 64GiB sector.
+    Large,
+}
+
+impl SecurityLevel {
+    /// Returns the number of nodes of graphs at this level.
+    pub fn nodes(self) -> usize {
+        match self {
+            SecurityLevel::Test => 1 << 6,
+            SecurityLevel::Standard => 1 << 30,
+            SecurityLevel::Large => 1 << 31,
+        }
+    }
+
+    /// Returns the base degree of graphs at this level.
+    pub fn base_degree(self) -> usize {
+        BASE_DEGREE
+    }
+}
+
+/// Creates a `BucketGraph` with the parameters of the given security level.
+pub fn bucket_graph_preset<H: Hasher>(
+    level: SecurityLevel,
+    porep_id: PoRepID,
+    api_version: ApiVersion,
+) -> Result<BucketGraph<H>> {
+    BucketGraph::new(level.nodes(), level.base_degree(), 0, porep_id, api_version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use filecoin_hashers::poseidon::PoseidonHasher;
+
+    use crate::drgraph::{derive_drg_seed, SeedPolicy};
+
+    #[test]
+    fn test_presets() {
+        for &level in &[
+            SecurityLevel::Test,
+            SecurityLevel::Standard,
+            SecurityLevel::Large,
+        ] {
+            let graph = bucket_graph_preset::<PoseidonHasher>(level, [1; 32], ApiVersion::V1_1_0)
+                .expect("bucket_graph_preset failure");
+            assert_eq!(graph.size(), level.nodes());
+            assert_eq!(level.base_degree(), BASE_DEGREE);
+            assert_eq!(graph.degree(), level.base_degree());
+            assert_eq!(graph.seed(), derive_drg_seed([1; 32]));
+            assert_eq!(graph.seed_policy(), SeedPolicy::Shared);
+
+            // Sample the first nodes, every parent must precede its child.
+            let mut parents = vec![0; graph.degree()];
+            for node in 2..64 {
+                graph.parents(node, &mut parents).expect("parents failure");
+                assert!(parents.iter().all(|&p| (p as usize) < node));
+            }
+        }
+
+        assert_eq!(SecurityLevel::Test.nodes(), 64);
+
+        // The test graph is small enough to check the parents of all of its nodes.
+        bucket_graph_preset::<PoseidonHasher>(SecurityLevel::Test, [1; 32], ApiVersion::V1_1_0)
+            .expect("bucket_graph_preset failure")
+            .verify_parents()
+            .expect("invalid parents");
+    }
+}
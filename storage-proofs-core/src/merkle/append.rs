@@ -0,0 +1,137 @@
+use filecoin_hashers::Hasher;
+
+use crate::merkle::hash_inner;
+
+/// A binary merkle tree that leaves can be appended to, exposing the root after every append.
+///
+/// Only the `O(height)` frontier of completed subtrees is stored. Missing leaves are padded
+/// with `H::Domain::default()` up to the next power of two, so once the number of appended
+/// leaves is a power of two the root matches the one of a `BinaryMerkleTree` over the same
+/// leaves.
+#[derive(Debug, Clone)]
+pub struct AppendTree<H: Hasher> {
+    /// The root of the completed subtree at every height, if any is pending a right sibling.
+    frontier: Vec<Option<H::Domain>>,
+    /// The root of an empty subtree at every height.
+    empty_roots: Vec<H::Domain>,
+    leaves: usize,
+}
+
+impl<H: Hasher> AppendTree<H> {
+    pub fn new() -> Self {
+        AppendTree {
+            frontier: Vec::new(),
+            empty_roots: vec![H::Domain::default()],
+            leaves: 0,
+        }
+    }
+
+    /// Returns the number of appended leaves.
+    pub fn leaves(&self) -> usize {
+        self.leaves
+    }
+
+    /// Appends a leaf and returns the new root.
+    pub fn append(&mut self, leaf: H::Domain) -> H::Domain {
+        let mut node = leaf;
+        let mut height = 0;
+        loop {
+            if self.frontier.len() <= height {
+                self.frontier.push(None);
+            }
+            match self.frontier[height].take() {
+                Some(left) => {
+                    node = hash_inner::<H>(&[left, node], height);
+                    height += 1;
+                }
+                None => {
+                    self.frontier[height] = Some(node);
+                    break;
+                }
+            }
+        }
+        self.leaves += 1;
+
+        // Make sure the padding is available for every level of the padded tree.
+        while self.empty_roots.len() < self.padded_height() {
+            let h = self.empty_roots.len() - 1;
+            let below = self.empty_roots[h];
+            self.empty_roots.push(hash_inner::<H>(&[below, below], h));
+        }
+
+        self.root().expect("tree is not empty")
+    }
+
+    /// Returns the current root, or `None` if no leaf was appended yet.
+    pub fn root(&self) -> Option<H::Domain> {
+        if self.leaves == 0 {
+            return None;
+        }
+
+        let height = self.padded_height();
+        if self.leaves > 1 && self.leaves.is_power_of_two() {
+            return self.frontier[height];
+        }
+
+        let mut node: Option<H::Domain> = None;
+        for h in 0..height {
+            let empty = self.empty_roots[h];
+            node = match (self.frontier.get(h).copied().flatten(), node) {
+                (Some(left), right) => Some(hash_inner::<H>(&[left, right.unwrap_or(empty)], h)),
+                (None, Some(left)) => Some(hash_inner::<H>(&[left, empty], h)),
+                (None, None) => None,
+            };
+        }
+
+        node
+    }
+
+    /// The number of levels above the leaves of the padded tree, which has at least two leaves.
+    fn padded_height(&self) -> usize {
+        self.leaves.next_power_of_two().trailing_zeros().max(1) as usize
+    }
+}
+
+impl<H: Hasher> Default for AppendTree<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use filecoin_hashers::{poseidon::PoseidonHasher, Domain};
+    use rand::thread_rng;
+
+    use crate::merkle::{BinaryMerkleTree, MerkleTreeTrait};
+
+    #[test]
+    fn test_append_tree() {
+        let mut rng = thread_rng();
+        let leaves: Vec<<PoseidonHasher as Hasher>::Domain> = (0..16)
+            .map(|_| <PoseidonHasher as Hasher>::Domain::random(&mut rng))
+            .collect();
+
+        let mut tree = AppendTree::<PoseidonHasher>::new();
+        assert!(tree.root().is_none());
+
+        let mut root = Default::default();
+        for (i, leaf) in leaves.iter().enumerate() {
+            root = tree.append(*leaf);
+            assert_eq!(tree.leaves(), i + 1);
+
+            // Compare against a tree padded to the next power of two.
+            let mut padded = leaves[..=i].to_vec();
+            padded.resize(padded.len().next_power_of_two().max(2), Default::default());
+            let expected = BinaryMerkleTree::<PoseidonHasher>::new(padded)
+                .expect("failed to build tree")
+                .root();
+            assert_eq!(root, expected, "wrong root after {} leaves", i + 1);
+        }
+
+        let batch = BinaryMerkleTree::<PoseidonHasher>::new(leaves).expect("failed to build tree");
+        assert_eq!(root, batch.root());
+    }
+}
@@ -0,0 +1,218 @@
+use anyhow::Result;
+use filecoin_hashers::Hasher;
+use generic_array::typenum::Unsigned;
+
+use crate::merkle::{hash_inner, MerkleProofTrait, MerkleTreeTrait};
+
+/// The proofs of many nodes of one tree, with all sibling hashes stored in a single contiguous
+/// buffer instead of one allocation per level and proof.
+///
+/// Individual proofs are accessed as `MerkleProofView`s borrowing into the arena.
+#[derive(Debug, Clone)]
+pub struct ProofArena<H: Hasher> {
+    root: H::Domain,
+    leaves: Vec<H::Domain>,
+    hashes: Vec<H::Domain>,
+    levels: Vec<ArenaLevel>,
+    /// The start of every proof in `levels` and in `hashes`.
+    offsets: Vec<(usize, usize)>,
+}
+
+impl<H: Hasher> ProofArena<H> {
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Returns a view of the `i`th proof.
+    ///
+    /// Panics if `i` is out of range.
+    pub fn get(&self, i: usize) -> MerkleProofView<'_, H> {
+        let (level_start, hash_start) = self.offsets[i];
+        let (level_end, hash_end) = self
+            .offsets
+            .get(i + 1)
+            .copied()
+            .unwrap_or((self.levels.len(), self.hashes.len()));
+
+        MerkleProofView {
+            leaf: self.leaves[i],
+            root: self.root,
+            levels: &self.levels[level_start..level_end],
+            hashes: &self.hashes[hash_start..hash_end],
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = MerkleProofView<'_, H>> + '_ {
+        (0..self.len()).map(move |i| self.get(i))
+    }
+}
+
+/// A level of a proof stored in a `ProofArena`.
+#[derive(Debug, Clone, Copy)]
+struct ArenaLevel {
+    siblings: usize,
+    index: usize,
+    /// The height passed to the hash function, which restarts at `0` for the sub and top tree
+    /// levels of compound trees.
+    height: usize,
+}
+
+/// A proof borrowed from a `ProofArena`, validating exactly like `MerkleProofTrait::validate`.
+#[derive(Debug, Clone, Copy)]
+pub struct MerkleProofView<'a, H: Hasher> {
+    leaf: H::Domain,
+    root: H::Domain,
+    levels: &'a [ArenaLevel],
+    hashes: &'a [H::Domain],
+}
+
+impl<'a, H: Hasher> MerkleProofView<'a, H> {
+    pub fn leaf(&self) -> H::Domain {
+        self.leaf
+    }
+
+    pub fn root(&self) -> H::Domain {
+        self.root
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Returns the node addressed by the indexes of the path.
+    pub fn path_index(&self) -> usize {
+        self.levels
+            .iter()
+            .rev()
+            .fold(0, |acc, level| acc * (level.siblings + 1) + level.index)
+    }
+
+    /// Calculates the root by folding the path, starting from the leaf.
+    pub fn calculate_root(&self) -> H::Domain {
+        let mut nodes = Vec::new();
+        let mut offset = 0;
+        self.levels.iter().fold(self.leaf, |h, level| {
+            nodes.clear();
+            nodes.extend_from_slice(&self.hashes[offset..offset + level.siblings]);
+            nodes.insert(level.index, h);
+            offset += level.siblings;
+
+            hash_inner::<H>(&nodes, level.height)
+        })
+    }
+
+    /// Validates that this view is a valid proof for `node`. An empty path is never valid.
+    pub fn validate(&self, node: usize) -> bool {
+        !self.levels.is_empty() && self.calculate_root() == self.root && node == self.path_index()
+    }
+}
+
+/// Generates the proofs of all `nodes` of `tree` into a single `ProofArena`, in the given order.
+pub fn gen_proofs_arena<Tree: MerkleTreeTrait>(
+    tree: &Tree,
+    nodes: &[usize],
+) -> Result<ProofArena<Tree::Hasher>> {
+    let mut arena = ProofArena {
+        root: tree.root(),
+        leaves: Vec::with_capacity(nodes.len()),
+        hashes: Vec::new(),
+        levels: Vec::new(),
+        offsets: Vec::with_capacity(nodes.len()),
+    };
+
+    // The sub and top tree each add a single level on top of the base tree path.
+    let compound_levels = [
+        Tree::SubTreeArity::to_usize(),
+        Tree::TopTreeArity::to_usize(),
+    ]
+    .iter()
+    .filter(|&&arity| arity > 0)
+    .count();
+
+    for &node in nodes {
+        let proof = tree.gen_proof(node)?;
+        let path = proof.path();
+        let base_levels = path.len() - compound_levels;
+
+        arena.offsets.push((arena.levels.len(), arena.hashes.len()));
+        arena.leaves.push(proof.leaf());
+        for (height, (siblings, index)) in path.into_iter().enumerate() {
+            arena.levels.push(ArenaLevel {
+                siblings: siblings.len(),
+                index,
+                height: if height < base_levels { height } else { 0 },
+            });
+            arena.hashes.extend(siblings);
+        }
+    }
+
+    Ok(arena)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use filecoin_hashers::poseidon::PoseidonHasher;
+    use generic_array::typenum::U2;
+    use rand::thread_rng;
+
+    use crate::merkle::{
+        generate_tree, get_base_tree_count, BinaryMerkleTree, BinarySubMerkleTree, MerkleProof,
+    };
+
+    fn views_validate_identically<Tree: 'static + MerkleTreeTrait>() {
+        let leaves = 64 * get_base_tree_count::<Tree>();
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<Tree, _>(&mut rng, leaves, None);
+
+        let nodes = [0, 5, 17, leaves - 1, 5];
+        let arena = gen_proofs_arena(&tree, &nodes).expect("gen_proofs_arena failure");
+        assert_eq!(arena.len(), nodes.len());
+
+        for (view, &node) in arena.iter().zip(nodes.iter()) {
+            let proof = tree.gen_proof(node).expect("gen_proof failure");
+            assert_eq!(view.leaf(), proof.leaf());
+            assert_eq!(view.root(), proof.root());
+            assert_eq!(view.len(), proof.path().len());
+            assert_eq!(view.path_index(), node);
+            assert_eq!(view.calculate_root(), proof.root());
+
+            for candidate in &[node, (node + 1) % leaves] {
+                assert_eq!(view.validate(*candidate), proof.validate(*candidate));
+            }
+        }
+
+        assert!(gen_proofs_arena(&tree, &[leaves]).is_err());
+    }
+
+    #[test]
+    fn test_proof_arena_binary() {
+        views_validate_identically::<BinaryMerkleTree<PoseidonHasher>>();
+    }
+
+    #[test]
+    fn test_proof_arena_binary_sub() {
+        views_validate_identically::<BinarySubMerkleTree<PoseidonHasher>>();
+    }
+
+    #[test]
+    fn test_proof_arena_empty_path() {
+        // The leaf of an empty proof equals its root, it is still invalid.
+        let proof = MerkleProof::<PoseidonHasher, U2>::new(0);
+        let view = MerkleProofView::<PoseidonHasher> {
+            leaf: proof.leaf(),
+            root: proof.root(),
+            levels: &[],
+            hashes: &[],
+        };
+        assert_eq!(view.calculate_root(), view.root());
+        assert_eq!(view.path_index(), 0);
+        assert!(!view.validate(0));
+        assert_eq!(view.validate(0), proof.validate(0));
+    }
+}
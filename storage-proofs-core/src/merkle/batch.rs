@@ -0,0 +1,901 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::{ensure, format_err, Result};
+use blstrs::Scalar as Fr;
+use filecoin_hashers::Hasher;
+use generic_array::typenum::Unsigned;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use merkletree::merkle::is_merkle_tree_size_valid;
+
+use crate::merkle::{
+    base_path_length, get_base_tree_count, hash_inner, MerkleProofTrait, MerkleTreeTrait,
+};
+
+/// Validates that every proof is valid for its paired node and that all of them are anchored to
+/// the same committed tree, identified by `expected_root`.
+///
+/// An empty set of proofs is trivially valid.
+pub fn validate_membership_set<P: MerkleProofTrait>(
+    proofs: &[(P, usize)],
+    expected_root: &<P::Hasher as Hasher>::Domain,
+) -> bool {
+    proofs
+        .iter()
+        .all(|(proof, node)| proof.root() == *expected_root && proof.validate(*node))
+}
+
+/// Validates like `validate_membership_set`, but for large batches such as the proofs of a PoSt
+/// challenge.
+///
+/// All roots are compared against `expected_root` before any path is hashed, so a proof of
+/// another tree rejects the batch cheaply. The paths are then validated in parallel.
+pub fn validate_batch<P: MerkleProofTrait>(
+    proofs: &[(P, usize)],
+    expected_root: &<P::Hasher as Hasher>::Domain,
+) -> bool {
+    proofs
+        .iter()
+        .all(|(proof, _)| proof.root() == *expected_root)
+        && proofs.par_iter().all(|(proof, node)| proof.validate(*node))
+}
+
+/// Reconstructs all leaves of the tree committed to by `expected_root` from one proof per leaf,
+/// each paired with its node.
+///
+/// Fails if a proof is invalid or of another tree, or if the nodes are not exactly all nodes of
+/// the tree.
+pub fn leaves_from_proofs<P: MerkleProofTrait>(
+    proofs: &[(P, usize)],
+    expected_root: &<P::Hasher as Hasher>::Domain,
+) -> Result<Vec<<P::Hasher as Hasher>::Domain>> {
+    let mut leaves = vec![None; proofs.len()];
+    for (proof, node) in proofs {
+        // The arities along the path determine the number of leaves of the tree.
+        let tree_leaves: usize = proof
+            .path()
+            .iter()
+            .map(|(siblings, _)| siblings.len() + 1)
+            .product();
+        ensure!(
+            tree_leaves == proofs.len(),
+            "expected {} proofs, got {}",
+            tree_leaves,
+            proofs.len()
+        );
+        ensure!(*node < leaves.len(), "node {} is out of range", node);
+        ensure!(
+            proof.root() == *expected_root && proof.validate(*node),
+            "invalid proof for node {}",
+            node
+        );
+        ensure!(leaves[*node].is_none(), "duplicate proof for node {}", node);
+        leaves[*node] = Some(proof.leaf());
+    }
+
+    // Every node is in range and unique, so all of them are present.
+    Ok(leaves.into_iter().flatten().collect())
+}
+
+/// A set of proofs arranged as circuit inputs, with roots shared between proofs deduplicated.
+#[derive(Debug, Clone, Default)]
+pub struct CircuitBatch {
+    /// The leaf of each proof.
+    pub leaves: Vec<Fr>,
+    /// The path of each proof, as returned by `MerkleProofTrait::as_options`.
+    pub paths: Vec<Vec<(Vec<Option<Fr>>, Option<usize>)>>,
+    /// The distinct roots, in order of first appearance.
+    pub roots: Vec<Fr>,
+    /// For each proof, the position of its root in `roots`.
+    pub root_indexes: Vec<usize>,
+}
+
+impl CircuitBatch {
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Returns the root of the `i`-th proof.
+    pub fn root(&self, i: usize) -> Fr {
+        self.roots[self.root_indexes[i]]
+    }
+}
+
+/// Arranges `proofs` into a [`CircuitBatch`], so proofs sharing a root only contribute a single
+/// root public input.
+pub fn as_circuit_batch<P: MerkleProofTrait>(proofs: &[P]) -> CircuitBatch {
+    let mut batch = CircuitBatch {
+        leaves: Vec::with_capacity(proofs.len()),
+        paths: Vec::with_capacity(proofs.len()),
+        roots: Vec::new(),
+        root_indexes: Vec::with_capacity(proofs.len()),
+    };
+    let mut known_roots: HashMap<<P::Hasher as Hasher>::Domain, usize> = HashMap::new();
+
+    for proof in proofs {
+        let root = proof.root();
+        let root_index = match known_roots.get(&root) {
+            Some(index) => *index,
+            None => {
+                let index = batch.roots.len();
+                batch.roots.push(root.into());
+                known_roots.insert(root, index);
+                index
+            }
+        };
+
+        batch.leaves.push(proof.leaf().into());
+        batch.paths.push(proof.as_options());
+        batch.root_indexes.push(root_index);
+    }
+
+    batch
+}
+
+/// Remembers which positions were already verified under a trusted root, so that repeated
+/// challenges of the same position can skip verification.
+#[derive(Debug, Clone, Default)]
+pub struct VerifiedSet<H: Hasher> {
+    verified: HashSet<(H::Domain, usize)>,
+}
+
+impl<H: Hasher> VerifiedSet<H> {
+    pub fn new() -> Self {
+        VerifiedSet {
+            verified: HashSet::new(),
+        }
+    }
+
+    /// Records that the proof of position `index` under `root` was verified.
+    pub fn mark_verified(&mut self, root: &H::Domain, index: usize) {
+        self.verified.insert((*root, index));
+    }
+
+    /// Returns true if position `index` under `root` was marked as verified.
+    pub fn is_verified(&self, root: &H::Domain, index: usize) -> bool {
+        self.verified.contains(&(*root, index))
+    }
+}
+
+/// Proves that the leaf at `index` differs between two versions of a tree.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeProof<P: MerkleProofTrait> {
+    pub index: usize,
+    #[serde(bound = "")]
+    pub old: P,
+    #[serde(bound = "")]
+    pub new: P,
+}
+
+impl<P: MerkleProofTrait> ChangeProof<P> {
+    /// Validates both inclusion proofs against the given roots, and that the leaves differ.
+    pub fn validate(
+        &self,
+        old_root: &<P::Hasher as Hasher>::Domain,
+        new_root: &<P::Hasher as Hasher>::Domain,
+    ) -> bool {
+        self.old.root() == *old_root
+            && self.new.root() == *new_root
+            && self.old.leaf() != self.new.leaf()
+            && self.old.validate(self.index)
+            && self.new.validate(self.index)
+    }
+}
+
+/// Generates a [`ChangeProof`] for the leaf at `index` of the `old` and `new` trees.
+pub fn prove_change<Tree: MerkleTreeTrait>(
+    old: &Tree,
+    new: &Tree,
+    index: usize,
+) -> Result<ChangeProof<Tree::Proof>> {
+    ensure!(
+        index < old.leaves() && index < new.leaves(),
+        "index {} is out of range",
+        index
+    );
+
+    Ok(ChangeProof {
+        index,
+        old: old.gen_proof(index)?,
+        new: new.gen_proof(index)?,
+    })
+}
+
+/// Returns the root `tree` would have with its leaf at `index` replaced by `new_leaf`, hashing
+/// only the nodes along the path of `index`. The tree itself is not modified.
+pub fn update_leaf<Tree: MerkleTreeTrait>(
+    tree: &Tree,
+    index: usize,
+    new_leaf: <Tree::Hasher as Hasher>::Domain,
+) -> Result<<Tree::Hasher as Hasher>::Domain> {
+    ensure!(index < tree.leaves(), "index {} is out of range", index);
+
+    let proof = tree.gen_proof(index)?;
+    Ok(proof
+        .path()
+        .into_iter()
+        .enumerate()
+        .fold(new_leaf, |h, (height, (mut nodes, index))| {
+            nodes.insert(index, h);
+            hash_inner::<Tree::Hasher>(&nodes, height)
+        }))
+}
+
+/// Proofs of nodes of the same tree, where the levels above an ancestor shared with an earlier
+/// proof are stored only once.
+///
+/// Nearby nodes share their high level ancestors, so their paths are identical from there on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct CoalescedProofs<H: Hasher> {
+    root: H::Domain,
+    proofs: Vec<CoalescedProof<H>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "")]
+struct CoalescedProof<H: Hasher> {
+    node: usize,
+    leaf: H::Domain,
+    /// The levels below the ancestor shared with `shared_with`, or the full path.
+    levels: Vec<(Vec<H::Domain>, usize)>,
+    /// The earlier proof providing the remaining levels.
+    shared_with: Option<usize>,
+}
+
+impl<H: Hasher> CoalescedProofs<H> {
+    pub fn len(&self) -> usize {
+        self.proofs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.proofs.is_empty()
+    }
+
+    /// Returns the nodes the proofs are paired with, in order.
+    pub fn nodes(&self) -> impl Iterator<Item = usize> + '_ {
+        self.proofs.iter().map(|proof| proof.node)
+    }
+
+    /// Returns the number of sibling hashes stored for all proofs.
+    pub fn hash_count(&self) -> usize {
+        self.proofs
+            .iter()
+            .flat_map(|proof| proof.levels.iter())
+            .map(|(siblings, _)| siblings.len())
+            .sum()
+    }
+
+    /// Reconstructs every proof and validates it for its node against `expected_root`.
+    pub fn validate_all(&self, expected_root: &H::Domain) -> bool {
+        if self.root != *expected_root {
+            return false;
+        }
+
+        let mut paths: Vec<Vec<(Vec<H::Domain>, usize)>> = Vec::with_capacity(self.proofs.len());
+        for (i, proof) in self.proofs.iter().enumerate() {
+            let mut path = proof.levels.clone();
+            if let Some(j) = proof.shared_with {
+                match paths.get(j) {
+                    Some(shared) if j < i && path.len() <= shared.len() => {
+                        path.extend_from_slice(&shared[path.len()..])
+                    }
+                    _ => return false,
+                }
+            }
+
+            // Deserialized proofs are untrusted, their indexes must fit their levels.
+            if path.is_empty() || path.iter().any(|(siblings, index)| *index > siblings.len()) {
+                return false;
+            }
+
+            let index = path.iter().rev().fold(0, |acc, (siblings, index)| {
+                acc * (siblings.len() + 1) + index
+            });
+            let root =
+                path.iter()
+                    .enumerate()
+                    .fold(proof.leaf, |h, (height, (siblings, index))| {
+                        let mut nodes = siblings.clone();
+                        nodes.insert(*index, h);
+                        hash_inner::<H>(&nodes, height)
+                    });
+            if index != proof.node || root != self.root {
+                return false;
+            }
+
+            paths.push(path);
+        }
+
+        true
+    }
+}
+
+/// Coalesces proofs of the same tree, each paired with its node, into [`CoalescedProofs`].
+///
+/// Every proof only keeps the levels below the lowest ancestor it shares with an earlier proof.
+/// Fails if the proofs have different roots or path shapes.
+pub fn coalesce<P: MerkleProofTrait>(proofs: &[(P, usize)]) -> Result<CoalescedProofs<P::Hasher>> {
+    let arities_of = |proof: &P| -> Vec<usize> {
+        proof
+            .path()
+            .iter()
+            .map(|(siblings, _)| siblings.len() + 1)
+            .collect()
+    };
+    let (root, arities) = match proofs.first() {
+        Some((proof, _)) => (proof.root(), arities_of(proof)),
+        None => Default::default(),
+    };
+    // For every level, the first proof passing through each ancestor at that level.
+    let mut ancestors: Vec<HashMap<usize, usize>> = vec![HashMap::new(); arities.len()];
+    let mut coalesced = Vec::with_capacity(proofs.len());
+
+    for (i, (proof, node)) in proofs.iter().enumerate() {
+        ensure!(proof.root() == root, "proof {} is of another tree", i);
+        ensure!(
+            arities_of(proof) == arities,
+            "proof {} has another shape",
+            i
+        );
+        let mut path = proof.path();
+
+        // The position of the running node at each level, starting with the leaf.
+        let mut positions = Vec::with_capacity(path.len());
+        let mut position = path.iter().rev().fold(0, |acc, (siblings, index)| {
+            acc * (siblings.len() + 1) + index
+        });
+        for arity in &arities {
+            positions.push(position);
+            position /= arity;
+        }
+
+        // Levels from the first shared ancestor up are identical to those of the earlier proof.
+        let shared = positions
+            .iter()
+            .zip(ancestors.iter())
+            .enumerate()
+            .find_map(|(level, (position, known))| known.get(position).map(|&j| (level, j)));
+        if let Some((level, _)) = shared {
+            path.truncate(level);
+        }
+
+        for (position, known) in positions.into_iter().zip(ancestors.iter_mut()) {
+            known.entry(position).or_insert(i);
+        }
+
+        coalesced.push(CoalescedProof {
+            node: *node,
+            leaf: proof.leaf(),
+            levels: path,
+            shared_with: shared.map(|(_, j)| j),
+        });
+    }
+
+    Ok(CoalescedProofs {
+        root,
+        proofs: coalesced,
+    })
+}
+
+/// Proofs of several leaves of the same tree, where every hash is stored at most once and the
+/// hashes derivable from the proven leaves are omitted entirely.
+///
+/// The positions along the paths follow from the proven indexes, and the shape of the tree from
+/// its type and number of leaves, so only the hashes are stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct MultiProof<H: Hasher> {
+    leaves: Vec<H::Domain>,
+    /// The siblings which are not derivable from the leaves, level by level in order of their
+    /// position.
+    hashes: Vec<H::Domain>,
+}
+
+impl<H: Hasher> MultiProof<H> {
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Returns the proven leaves, in the order of the indexes the proof was generated for.
+    pub fn leaves(&self) -> &[H::Domain] {
+        &self.leaves
+    }
+
+    /// Returns the number of sibling hashes stored.
+    pub fn hash_count(&self) -> usize {
+        self.hashes.len()
+    }
+
+    /// Validates that the leaves are the leaves at `indices` of the `Tree` of `leaves` leaves
+    /// committed to by `root`.
+    ///
+    /// `indices` must be given in the order the proof was generated for.
+    pub fn verify<Tree: MerkleTreeTrait<Hasher = H>>(
+        &self,
+        root: &H::Domain,
+        leaves: usize,
+        indices: &[usize],
+    ) -> bool {
+        let levels = match tree_levels::<Tree>(leaves) {
+            Some(levels) => levels,
+            None => return false,
+        };
+        if indices.is_empty() || indices.len() != self.leaves.len() {
+            return false;
+        }
+
+        let mut known = BTreeMap::new();
+        for (&index, &leaf) in indices.iter().zip(self.leaves.iter()) {
+            if index >= leaves || known.insert(index, leaf).is_some() {
+                return false;
+            }
+        }
+
+        let mut hashes = self.hashes.iter();
+        for (arity, height) in levels {
+            let mut parents = BTreeMap::new();
+            let mut children = Vec::with_capacity(arity);
+            let mut known_iter = known.into_iter().peekable();
+
+            while let Some(&(position, _)) = known_iter.peek() {
+                let first = position - position % arity;
+                children.clear();
+                for child in first..first + arity {
+                    if let Some((_, hash)) = known_iter.next_if(|(p, _)| *p == child) {
+                        children.push(hash);
+                    } else if let Some(&hash) = hashes.next() {
+                        children.push(hash);
+                    } else {
+                        return false;
+                    }
+                }
+                parents.insert(first / arity, hash_inner::<H>(&children, height));
+            }
+
+            known = parents;
+        }
+
+        hashes.next().is_none() && known.len() == 1 && known.get(&0) == Some(root)
+    }
+}
+
+/// Returns the arity and the hashing height of every level of a `Tree` of `leaves` leaves,
+/// starting at the leaves, or `None` if no such tree exists. The height restarts at `0` for the
+/// sub and top tree levels of compound trees.
+fn tree_levels<Tree: MerkleTreeTrait>(leaves: usize) -> Option<Vec<(usize, usize)>> {
+    let base_trees = get_base_tree_count::<Tree>();
+    let base_leaves = leaves / base_trees;
+    if base_leaves < 2
+        || base_leaves * base_trees != leaves
+        || !is_merkle_tree_size_valid(base_leaves, Tree::Arity::to_usize())
+    {
+        return None;
+    }
+
+    let base_levels =
+        base_path_length::<Tree::Arity, Tree::SubTreeArity, Tree::TopTreeArity>(leaves);
+    let mut levels = (0..base_levels)
+        .map(|height| (Tree::Arity::to_usize(), height))
+        .collect::<Vec<_>>();
+    // The sub and top tree each add a single level on top of the base tree path.
+    for &arity in &[
+        Tree::SubTreeArity::to_usize(),
+        Tree::TopTreeArity::to_usize(),
+    ] {
+        if arity > 0 {
+            levels.push((arity, 0));
+        }
+    }
+
+    Some(levels)
+}
+
+/// Generates a [`MultiProof`] of the leaves at `indices` of `tree`.
+///
+/// Fails if an index is out of range or repeated.
+pub fn gen_multi_proof<Tree: MerkleTreeTrait>(
+    tree: &Tree,
+    indices: &[usize],
+) -> Result<MultiProof<Tree::Hasher>> {
+    ensure!(!indices.is_empty(), "no leaves to prove");
+    let levels = tree_levels::<Tree>(tree.leaves())
+        .ok_or_else(|| format_err!("invalid tree of {} leaves", tree.leaves()))?;
+
+    let mut leaves = Vec::with_capacity(indices.len());
+    let mut paths = Vec::with_capacity(indices.len());
+    // The position of every known node of the current level, and a proof passing through it.
+    let mut known = BTreeMap::new();
+    for (i, &index) in indices.iter().enumerate() {
+        ensure!(
+            known.insert(index, i).is_none(),
+            "leaf {} is repeated",
+            index
+        );
+        let proof = tree.gen_proof(index)?;
+        leaves.push(proof.leaf());
+        paths.push(proof.path());
+    }
+
+    let mut hashes = Vec::new();
+    for (level, &(arity, _)) in levels.iter().enumerate() {
+        let mut parents = BTreeMap::new();
+        for (&position, &i) in &known {
+            parents.entry(position / arity).or_insert(i);
+        }
+
+        for (&parent, &i) in &parents {
+            let (siblings, own) = &paths[i][level];
+            for offset in 0..arity {
+                if offset != *own && !known.contains_key(&(parent * arity + offset)) {
+                    let sibling = if offset < *own { offset } else { offset - 1 };
+                    hashes.push(siblings[sibling]);
+                }
+            }
+        }
+
+        known = parents;
+    }
+
+    Ok(MultiProof { leaves, hashes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use filecoin_hashers::{poseidon::PoseidonHasher, Domain, PoseidonArity};
+    use generic_array::typenum::{U2, U8};
+    use rand::thread_rng;
+
+    use crate::merkle::{
+        generate_tree, get_base_tree_count, BinaryMerkleTree, BinarySubMerkleTree, MerkleTree,
+    };
+
+    #[test]
+    fn test_validate_batch() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 64, None);
+        let (_, other_tree) =
+            generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 64, None);
+        let root = tree.root();
+
+        let proofs = (0..64)
+            .step_by(3)
+            .map(|i| (tree.gen_proof(i).expect("gen_proof failure"), i))
+            .collect::<Vec<_>>();
+        assert!(validate_batch(&proofs, &root));
+        assert!(validate_batch(&proofs[..0], &root));
+        assert!(!validate_batch(&proofs, &other_tree.root()));
+
+        // A single proof paired with the wrong node rejects the batch.
+        let mut misplaced = proofs.clone();
+        misplaced[5].1 += 1;
+        assert!(!validate_batch(&misplaced, &root));
+
+        // As does a single valid proof of another tree.
+        let mut foreign = proofs.clone();
+        foreign[9] = (other_tree.gen_proof(27).expect("gen_proof failure"), 27);
+        assert!(!validate_batch(&foreign, &root));
+
+        for batch in &[&proofs, &misplaced, &foreign] {
+            assert_eq!(
+                validate_batch(batch, &root),
+                validate_membership_set(batch, &root)
+            );
+        }
+    }
+
+    fn update_leaf_matches_rebuild<U: 'static + PoseidonArity>() {
+        let mut rng = thread_rng();
+        let mut leaves = (0..64)
+            .map(|_| <PoseidonHasher as Hasher>::Domain::random(&mut rng))
+            .collect::<Vec<_>>();
+        let tree = MerkleTree::<PoseidonHasher, U>::new(leaves.clone()).expect("new failure");
+        let root = tree.root();
+
+        for &index in &[0, 13, 63] {
+            let new_leaf = <PoseidonHasher as Hasher>::Domain::random(&mut rng);
+            let updated = update_leaf(&tree, index, new_leaf).expect("update_leaf failure");
+
+            let old_leaf = std::mem::replace(&mut leaves[index], new_leaf);
+            let rebuilt =
+                MerkleTree::<PoseidonHasher, U>::new(leaves.clone()).expect("new failure");
+            leaves[index] = old_leaf;
+
+            assert_eq!(updated, rebuilt.root());
+            assert_ne!(updated, root);
+            assert_eq!(tree.root(), root);
+            assert_eq!(
+                update_leaf(&tree, index, leaves[index]).expect("update_leaf failure"),
+                root
+            );
+        }
+
+        assert!(update_leaf(&tree, 64, root).is_err());
+    }
+
+    #[test]
+    fn test_update_leaf_binary() {
+        update_leaf_matches_rebuild::<U2>();
+    }
+
+    #[test]
+    fn test_update_leaf_oct() {
+        update_leaf_matches_rebuild::<U8>();
+    }
+
+    #[test]
+    fn test_validate_membership_set() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 32, None);
+        let (_, other_tree) =
+            generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 32, None);
+
+        let mut proofs = [1, 7, 30]
+            .iter()
+            .map(|&i| (tree.gen_proof(i).expect("gen_proof failure"), i))
+            .collect::<Vec<_>>();
+        assert!(validate_membership_set(&proofs, &tree.root()));
+
+        // A valid proof of a different tree must make the whole set fail.
+        proofs.push((other_tree.gen_proof(3).expect("gen_proof failure"), 3));
+        assert!(!validate_membership_set(&proofs, &tree.root()));
+    }
+
+    #[test]
+    fn test_as_circuit_batch() {
+        let mut rng = thread_rng();
+        let (_, tree_a) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 32, None);
+        let (_, tree_b) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 32, None);
+
+        let proofs = vec![
+            tree_a.gen_proof(0).expect("gen_proof failure"),
+            tree_b.gen_proof(4).expect("gen_proof failure"),
+            tree_a.gen_proof(9).expect("gen_proof failure"),
+            tree_b.gen_proof(31).expect("gen_proof failure"),
+        ];
+
+        let batch = as_circuit_batch(&proofs);
+        assert_eq!(batch.len(), proofs.len());
+        assert_eq!(batch.roots.len(), 2);
+        assert_eq!(batch.root_indexes, vec![0, 1, 0, 1]);
+
+        for (i, proof) in proofs.iter().enumerate() {
+            assert_eq!(batch.paths[i], proof.as_options());
+            assert_eq!(batch.leaves[i], Into::<Fr>::into(proof.leaf()));
+            assert_eq!(batch.root(i), Into::<Fr>::into(proof.root()));
+        }
+    }
+
+    #[test]
+    fn test_verified_set() {
+        let mut rng = thread_rng();
+        let (_, tree_a) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 32, None);
+        let (_, tree_b) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 32, None);
+        let (root_a, root_b) = (tree_a.root(), tree_b.root());
+
+        let mut verified = VerifiedSet::<PoseidonHasher>::new();
+        assert!(!verified.is_verified(&root_a, 5));
+
+        let proof = tree_a.gen_proof(5).expect("gen_proof failure");
+        assert!(proof.validate(5));
+        verified.mark_verified(&proof.root(), 5);
+
+        assert!(verified.is_verified(&root_a, 5));
+        assert!(!verified.is_verified(&root_a, 6));
+        assert!(!verified.is_verified(&root_b, 5));
+
+        // Marking again is idempotent.
+        verified.mark_verified(&root_a, 5);
+        assert!(verified.is_verified(&root_a, 5));
+    }
+
+    #[test]
+    fn test_prove_change() {
+        let mut rng = thread_rng();
+        let mut leaves: Vec<<PoseidonHasher as Hasher>::Domain> = (0..16)
+            .map(|_| <PoseidonHasher as Hasher>::Domain::random(&mut rng))
+            .collect();
+        let old =
+            BinaryMerkleTree::<PoseidonHasher>::new(leaves.clone()).expect("failed to build tree");
+        leaves[6] = <PoseidonHasher as Hasher>::Domain::random(&mut rng);
+        let new = BinaryMerkleTree::<PoseidonHasher>::new(leaves).expect("failed to build tree");
+        let (old_root, new_root) = (old.root(), new.root());
+
+        let change = prove_change(&old, &new, 6).expect("prove_change failure");
+        assert!(change.validate(&old_root, &new_root));
+        assert!(!change.validate(&new_root, &old_root));
+
+        // An unchanged position can't be proven to have changed.
+        let unchanged = prove_change(&old, &new, 7).expect("prove_change failure");
+        assert!(!unchanged.validate(&old_root, &new_root));
+
+        assert!(prove_change(&old, &new, 16).is_err());
+    }
+
+    #[test]
+    fn test_leaves_from_proofs() {
+        let mut rng = thread_rng();
+        let leaves: Vec<<PoseidonHasher as Hasher>::Domain> = (0..8)
+            .map(|_| <PoseidonHasher as Hasher>::Domain::random(&mut rng))
+            .collect();
+        let tree =
+            BinaryMerkleTree::<PoseidonHasher>::new(leaves.clone()).expect("failed to build tree");
+        let root = tree.root();
+
+        // Proofs may come in any order.
+        let mut proofs = (0..8)
+            .rev()
+            .map(|i| (tree.gen_proof(i).expect("gen_proof failure"), i))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            leaves_from_proofs(&proofs, &root).expect("leaves_from_proofs failure"),
+            leaves
+        );
+
+        let (_, other_tree) =
+            generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 8, None);
+        assert!(leaves_from_proofs(&proofs, &other_tree.root()).is_err());
+
+        // A gap filled by a duplicate.
+        proofs[0] = proofs[1].clone();
+        assert!(leaves_from_proofs(&proofs, &root).is_err());
+
+        // Missing proofs.
+        assert!(leaves_from_proofs(&proofs[1..], &root).is_err());
+        assert!(leaves_from_proofs(&proofs[4..], &root).is_err());
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 32, None);
+        let root = tree.root();
+
+        let proofs = [4, 5, 30, 4]
+            .iter()
+            .map(|&i| (tree.gen_proof(i).expect("gen_proof failure"), i))
+            .collect::<Vec<_>>();
+        let coalesced = coalesce(&proofs).expect("coalesce failure");
+        assert_eq!(coalesced.len(), proofs.len());
+        assert!(coalesced.validate_all(&root));
+
+        // Node 5 only keeps its lowest level and the repeated node 4 nothing at all.
+        let concatenated: usize = proofs
+            .iter()
+            .flat_map(|(proof, _)| proof.path())
+            .map(|(siblings, _)| siblings.len())
+            .sum();
+        assert_eq!(concatenated, 4 * 5);
+        assert_eq!(coalesced.hash_count(), 5 + 1 + 5);
+
+        let (_, other_tree) =
+            generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 32, None);
+        assert!(!coalesced.validate_all(&other_tree.root()));
+
+        // Proofs must be paired with their nodes.
+        let mut misplaced = proofs.clone();
+        misplaced[1].1 = 6;
+        assert!(!coalesce(&misplaced)
+            .expect("coalesce failure")
+            .validate_all(&root));
+
+        let mut mixed = proofs;
+        mixed.push((other_tree.gen_proof(3).expect("gen_proof failure"), 3));
+        assert!(coalesce(&mixed).is_err());
+
+        let json = serde_json::to_string(&coalesced).expect("serialize failure");
+        let tampered = |tamper: &dyn Fn(&mut CoalescedProofs<PoseidonHasher>)| {
+            let mut coalesced: CoalescedProofs<PoseidonHasher> =
+                serde_json::from_str(&json).expect("deserialize failure");
+            tamper(&mut coalesced);
+            coalesced.validate_all(&coalesced.root)
+        };
+        assert!(tampered(&|_| ()));
+
+        // An index beyond the siblings of its level.
+        assert!(!tampered(&|coalesced| coalesced.proofs[0].levels[0].1 = 2));
+        assert!(!tampered(
+            &|coalesced| coalesced.proofs[0].levels[0].1 = usize::MAX
+        ));
+
+        // A proof without a path, whose leaf is the root.
+        assert!(!tampered(&|coalesced| {
+            coalesced.proofs.truncate(1);
+            coalesced.proofs[0].levels.clear();
+            coalesced.proofs[0].leaf = coalesced.root;
+            coalesced.proofs[0].node = 0;
+        }));
+    }
+
+    fn multi_proof_matches_proofs<Tree: 'static + MerkleTreeTrait>() {
+        let leaves = 64 * get_base_tree_count::<Tree>();
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<Tree, _>(&mut rng, leaves, None);
+        let root = tree.root();
+
+        let indices = [3, 4, 5, 6, 7, 40, leaves - 1];
+        let multi = gen_multi_proof(&tree, &indices).expect("gen_multi_proof failure");
+        assert_eq!(multi.len(), indices.len());
+
+        let proofs = indices
+            .iter()
+            .map(|&i| tree.gen_proof(i).expect("gen_proof failure"))
+            .collect::<Vec<_>>();
+        assert!(proofs
+            .iter()
+            .zip(indices.iter())
+            .all(|(proof, &i)| proof.validate(i)));
+        assert!(multi.verify::<Tree>(&root, leaves, &indices));
+        for (leaf, proof) in multi.leaves().iter().zip(proofs.iter()) {
+            assert_eq!(*leaf, proof.leaf());
+        }
+
+        let concatenated: usize = proofs
+            .iter()
+            .map(|proof| bincode::serialize(proof).expect("serialize failure").len())
+            .sum();
+        assert!(bincode::serialize(&multi).expect("serialize failure").len() < concatenated);
+
+        // The indexes must be the ones proven, in the same order.
+        let mut swapped = indices;
+        swapped.swap(0, 5);
+        assert!(!multi.verify::<Tree>(&root, leaves, &swapped));
+        let mut moved = indices;
+        moved[5] += 1;
+        assert!(!multi.verify::<Tree>(&root, leaves, &moved));
+        assert!(!multi.verify::<Tree>(&root, leaves, &indices[1..]));
+
+        let (_, other_tree) = generate_tree::<Tree, _>(&mut rng, leaves, None);
+        assert!(!multi.verify::<Tree>(&other_tree.root(), leaves, &indices));
+
+        // The shape of the tree is not taken from the proof: presenting the root or an interior
+        // node as a leaf, i.e. the proof of an empty or truncated path, does not verify.
+        let root_as_leaf = MultiProof::<Tree::Hasher> {
+            leaves: vec![root],
+            hashes: Vec::new(),
+        };
+        assert!(!root_as_leaf.verify::<Tree>(&root, leaves, &[0]));
+        let truncated = MultiProof::<Tree::Hasher> {
+            leaves: vec![proofs[0].level_hashes()[1]],
+            hashes: proofs[0]
+                .path()
+                .into_iter()
+                .skip(1)
+                .flat_map(|(siblings, _)| siblings)
+                .collect(),
+        };
+        let interior = indices[0] / Tree::Arity::to_usize();
+        assert!(!truncated.verify::<Tree>(&root, leaves, &[interior]));
+
+        // Indexes out of range and impossible tree sizes are rejected.
+        assert!(!multi.verify::<Tree>(&root, leaves, &[0, 1, 2, 3, 4, 5, leaves]));
+        for &size in &[0, 1, leaves / 2 + 1, leaves + 1] {
+            assert!(!multi.verify::<Tree>(&root, size, &indices));
+        }
+
+        assert!(gen_multi_proof(&tree, &[1, 2, 1]).is_err());
+        assert!(gen_multi_proof(&tree, &[leaves]).is_err());
+        assert!(gen_multi_proof(&tree, &[]).is_err());
+    }
+
+    #[test]
+    fn test_multi_proof_binary() {
+        multi_proof_matches_proofs::<BinaryMerkleTree<PoseidonHasher>>();
+    }
+
+    #[test]
+    fn test_multi_proof_binary_sub() {
+        multi_proof_matches_proofs::<BinarySubMerkleTree<PoseidonHasher>>();
+    }
+}
@@ -1,26 +1,35 @@
 use std::any::Any;
 use std::fs::File;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::mem::size_of;
 use std::path::PathBuf;
 
 use anyhow::ensure;
-use filecoin_hashers::{Domain, Hasher, PoseidonArity};
+use filecoin_hashers::{
+    blake2s::Blake2sHasher, poseidon::PoseidonHasher, sha256::Sha256Hasher, Domain, HashFunction,
+    HashKind, Hasher, PoseidonArity,
+};
 use generic_array::typenum::{Unsigned, U0};
 use log::trace;
 use merkletree::{
     merkle::{
-        get_merkle_tree_leafs, is_merkle_tree_size_valid, FromIndexedParallelIterator, MerkleTree,
+        get_merkle_tree_leafs, get_merkle_tree_len, is_merkle_tree_size_valid,
+        FromIndexedParallelIterator, MerkleTree,
     },
     store::{DiskStore, ExternalReader, LevelCacheStore, ReplicaConfig, Store, StoreConfig},
 };
 use rand::Rng;
-use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use rayon::prelude::{
+    IndexedParallelIterator, IntoParallelIterator, ParallelIterator, ParallelSlice,
+};
 
 use crate::{
     error::{Error, Result},
-    merkle::{DiskTree, LCMerkleTree, LCStore, LCTree, MerkleTreeTrait, MerkleTreeWrapper},
-    util::{data_at_node, default_rows_to_discard, NODE_SIZE},
+    merkle::{
+        hash_inner, BinaryMerkleTree, DiskTree, LCMerkleTree, LCStore, LCTree, MerkleTreeTrait,
+        MerkleTreeWrapper,
+    },
+    util::{data_at_node_ref, default_rows_to_discard, NODE_SIZE},
 };
 
 // Create a DiskTree from the provided config(s), each representing a 'base' layer tree with 'base_tree_len' elements.
@@ -171,6 +180,23 @@ where
     }
 }
 
+/// The smallest number of leaves worth hashing as one parallel task.
+const MIN_CHUNK_SIZE: usize = 1024;
+
+/// The number of tasks per thread, so that threads finishing early can pick up more work.
+const CHUNKS_PER_THREAD: usize = 4;
+
+/// Returns the number of leaves hashed as one parallel task when building a tree of `leaf_count`
+/// leaves on the current rayon thread pool. Never returns 0.
+pub fn recommended_chunk_size(leaf_count: usize) -> usize {
+    chunk_size_for_threads(leaf_count, rayon::current_num_threads())
+}
+
+fn chunk_size_for_threads(leaf_count: usize, threads: usize) -> usize {
+    let chunk_size = leaf_count / (threads.max(1) * CHUNKS_PER_THREAD);
+    chunk_size.max(MIN_CHUNK_SIZE).min(leaf_count).max(1)
+}
+
 // Note: This method verifies that the tree can be build with the size
 // specified.  If the data on disk is longer, this method is safe to
 // use on the first 'size' nodes.
@@ -184,6 +210,13 @@ pub fn create_base_merkle_tree<Tree: MerkleTreeTrait>(
         Error::InvalidMerkleTreeArgs(data.len(), NODE_SIZE, size)
     );
 
+    // A single leaf is accepted by `is_merkle_tree_size_valid`, but its proof has an empty path.
+    ensure!(
+        size > 1,
+        "a merkle tree needs at least 2 leaves, got {}",
+        size
+    );
+
     trace!("create_merkle_tree called with size {}", size);
     trace!(
         "is_merkle_tree_size_valid({}, arity {}) = {}",
@@ -198,7 +231,7 @@ pub fn create_base_merkle_tree<Tree: MerkleTreeTrait>(
 
     let f = |i| {
         // TODO Replace `expect()` with `context()` (problem is the parallel iterator)
-        let d = data_at_node(data, i).expect("data_at_node math failed");
+        let d = data_at_node_ref(data, i, NODE_SIZE).expect("data_at_node math failed");
         // TODO/FIXME: This can panic. FOR NOW, let's leave this since we're experimenting with
         // optimization paths. However, we need to ensure that bad input will not lead to a panic
         // that isn't caught by the FPS API.
@@ -216,7 +249,13 @@ pub fn create_base_merkle_tree<Tree: MerkleTreeTrait>(
             Tree::Arity,
             Tree::SubTreeArity,
             Tree::TopTreeArity,
-        >::from_par_iter_with_config((0..size).into_par_iter().map(f), x),
+        >::from_par_iter_with_config(
+            (0..size)
+                .into_par_iter()
+                .with_min_len(recommended_chunk_size(size))
+                .map(f),
+            x,
+        ),
         None => MerkleTree::<
             <Tree::Hasher as Hasher>::Domain,
             <Tree::Hasher as Hasher>::Function,
@@ -224,12 +263,466 @@ pub fn create_base_merkle_tree<Tree: MerkleTreeTrait>(
             Tree::Arity,
             Tree::SubTreeArity,
             Tree::TopTreeArity,
-        >::from_par_iter((0..size).into_par_iter().map(f)),
+        >::from_par_iter(
+            (0..size)
+                .into_par_iter()
+                .with_min_len(recommended_chunk_size(size))
+                .map(f),
+        ),
     }?;
 
     Ok(Tree::from_merkle(tree))
 }
 
+/// Returns the number of leaves of the smallest base tree of the given arity with at least
+/// `leaves` leaves.
+pub fn padded_leaf_count(leaves: usize, arity: usize) -> usize {
+    let mut count = arity;
+    while count < leaves {
+        count *= arity;
+    }
+    count
+}
+
+/// Like `create_base_merkle_tree`, but `size` may be any positive number of nodes. The leaves are
+/// padded with zero domain elements up to `padded_leaf_count`, so the proofs of all `size` real
+/// nodes validate against the padded root.
+///
+/// Zero leaves never are encoded data, see `MerkleProofTrait::validate_non_zero`. Compound trees
+/// are not supported, as their base trees must have equal sizes.
+pub fn create_padded_base_merkle_tree<Tree: MerkleTreeTrait>(
+    config: Option<StoreConfig>,
+    size: usize,
+    data: &[u8],
+) -> Result<Tree> {
+    ensure!(
+        data.len() == NODE_SIZE * size,
+        Error::InvalidMerkleTreeArgs(data.len(), NODE_SIZE, size)
+    );
+    ensure!(size > 0, "cannot build a tree without nodes");
+    ensure!(
+        Tree::SubTreeArity::to_usize() == 0,
+        "compound trees cannot be padded"
+    );
+
+    let mut leaves = data
+        .par_chunks(NODE_SIZE)
+        .map(<Tree::Hasher as Hasher>::Domain::try_from_bytes)
+        .collect::<Result<Vec<_>>>()?;
+    leaves.resize(
+        padded_leaf_count(size, Tree::Arity::to_usize()),
+        Default::default(),
+    );
+
+    tree_from_leaves(config, leaves)
+}
+
+/// Builds a binary base tree over `data` like `create_base_merkle_tree`, with the hash function
+/// selected at runtime by `kind`, and returns the bytes of its root.
+///
+/// This allows tooling and tests to switch to a faster hash without being recompiled.
+pub fn create_base_merkle_tree_root(kind: HashKind, size: usize, data: &[u8]) -> Result<Vec<u8>> {
+    let root = match kind {
+        HashKind::Poseidon => {
+            create_base_merkle_tree::<BinaryMerkleTree<PoseidonHasher>>(None, size, data)?
+                .root()
+                .into_bytes()
+        }
+        HashKind::Sha256 => {
+            create_base_merkle_tree::<BinaryMerkleTree<Sha256Hasher>>(None, size, data)?
+                .root()
+                .into_bytes()
+        }
+        HashKind::Blake2s => {
+            create_base_merkle_tree::<BinaryMerkleTree<Blake2sHasher>>(None, size, data)?
+                .root()
+                .into_bytes()
+        }
+    };
+
+    Ok(root)
+}
+
+/// Like `create_base_merkle_tree`, but the leaves are hashed in `chunk_count` contiguous chunks,
+/// which are processed independently and reassembled in order before building the tree.
+///
+/// Keeping each worker on a contiguous range of the input improves cache and NUMA locality on
+/// large machines. The resulting tree is identical to the one of `create_base_merkle_tree`.
+pub fn create_base_merkle_tree_chunked<Tree: MerkleTreeTrait>(
+    config: Option<StoreConfig>,
+    size: usize,
+    data: &[u8],
+    chunk_count: usize,
+) -> Result<Tree> {
+    ensure!(
+        data.len() == (NODE_SIZE * size) as usize,
+        Error::InvalidMerkleTreeArgs(data.len(), NODE_SIZE, size)
+    );
+    ensure!(
+        size > 1,
+        "a merkle tree needs at least 2 leaves, got {}",
+        size
+    );
+    ensure!(
+        is_merkle_tree_size_valid(size, Tree::Arity::to_usize()),
+        "Invalid merkle tree size given the arity"
+    );
+    ensure!(chunk_count > 0, "chunk_count must be greater than zero");
+
+    // Chunk boundaries are aligned to whole nodes.
+    let nodes_per_chunk = (size + chunk_count - 1) / chunk_count;
+    let chunks = data
+        .par_chunks(nodes_per_chunk * NODE_SIZE)
+        .map(|chunk| {
+            chunk
+                .chunks(NODE_SIZE)
+                .map(<Tree::Hasher as Hasher>::Domain::try_from_bytes)
+                .collect::<Result<Vec<_>>>()
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let leaves = chunks.into_iter().flatten().collect::<Vec<_>>();
+
+    tree_from_leaves(config, leaves)
+}
+
+/// Like `create_base_merkle_tree`, but the `size` nodes are read from `reader` one at a time
+/// instead of requiring all data in memory. With a `config`, the tree is built on disk.
+///
+/// Fails if `reader` yields fewer than `NODE_SIZE * size` bytes, any bytes past those are left
+/// unread. The tree is built next to the store of `config` and only moved into place once all
+/// nodes were read, so a failure never leaves a tree at the store path.
+pub fn create_base_merkle_tree_from_reader<Tree: MerkleTreeTrait, R: Read>(
+    config: Option<StoreConfig>,
+    size: usize,
+    mut reader: R,
+) -> Result<Tree> {
+    ensure!(
+        size > 1,
+        "a merkle tree needs at least 2 leaves, got {}",
+        size
+    );
+    ensure!(
+        is_merkle_tree_size_valid(size, Tree::Arity::to_usize()),
+        "Invalid merkle tree size given the arity"
+    );
+
+    // The tree is built from an iterator, so the first error is recorded and reported once the
+    // iteration is done.
+    let mut error = None;
+    let mut node = [0u8; NODE_SIZE];
+    let leaves = (0..size).map(|_| {
+        if error.is_none() {
+            match reader
+                .read_exact(&mut node)
+                .map_err(Into::into)
+                .and_then(|_| <Tree::Hasher as Hasher>::Domain::try_from_bytes(&node))
+            {
+                Ok(leaf) => return leaf,
+                Err(err) => error = Some(err),
+            }
+        }
+        Default::default()
+    });
+
+    let config = match config {
+        Some(config) => config,
+        None => {
+            let tree = MerkleTree::<
+                <Tree::Hasher as Hasher>::Domain,
+                <Tree::Hasher as Hasher>::Function,
+                Tree::Store,
+                Tree::Arity,
+                Tree::SubTreeArity,
+                Tree::TopTreeArity,
+            >::new(leaves);
+            if let Some(err) = error {
+                return Err(err);
+            }
+
+            return Ok(Tree::from_merkle(tree?));
+        }
+    };
+
+    let partial = StoreConfig::from_config(&config, format!("{}-partial", config.id), None);
+    let partial_path = StoreConfig::data_path(&partial.path, &partial.id);
+    let tree = MerkleTree::<
+        <Tree::Hasher as Hasher>::Domain,
+        <Tree::Hasher as Hasher>::Function,
+        Tree::Store,
+        Tree::Arity,
+        Tree::SubTreeArity,
+        Tree::TopTreeArity,
+    >::new_with_config(leaves, partial);
+    let tree = match (error, tree) {
+        (None, Ok(tree)) => tree,
+        (Some(err), _) | (None, Err(err)) => {
+            // The partial tree may not have been written at all.
+            let _ = std::fs::remove_file(&partial_path);
+            return Err(err);
+        }
+    };
+
+    // Close the partial store before moving it into place and reopening it there.
+    drop(tree);
+    std::fs::rename(
+        &partial_path,
+        StoreConfig::data_path(&config.path, &config.id),
+    )?;
+    let tree_len = get_merkle_tree_len(size, Tree::Arity::to_usize())?;
+    let store = Tree::Store::new_from_disk(tree_len, Tree::Arity::to_usize(), &config)?;
+
+    Ok(Tree::from_merkle(MerkleTree::from_data_store(store, size)?))
+}
+
+/// The smallest node size accepted by `create_base_merkle_tree_with_node_size`.
+pub const MIN_NODE_SIZE: usize = 8;
+
+/// Like `create_base_merkle_tree`, but `data` is split into nodes of `node_size` bytes, which
+/// must evenly divide it. Every node is zero padded to `NODE_SIZE` bytes to form its leaf, so e.g.
+/// 31 byte nodes always are valid field elements.
+///
+/// `node_size` must be in `MIN_NODE_SIZE..=NODE_SIZE`, for `NODE_SIZE` the tree is identical to
+/// the one of `create_base_merkle_tree`. Larger nodes are rejected, as a leaf is a single domain
+/// element of `NODE_SIZE` bytes: they must be hashed into a leaf first, see
+/// `create_merkle_tree_with_leaf_hasher` and `create_hybrid_merkle_tree`.
+pub fn create_base_merkle_tree_with_node_size<Tree: MerkleTreeTrait>(
+    config: Option<StoreConfig>,
+    data: &[u8],
+    node_size: usize,
+) -> Result<Tree> {
+    ensure!(
+        (MIN_NODE_SIZE..=NODE_SIZE).contains(&node_size),
+        "node_size must be between {} and {}, got {}",
+        MIN_NODE_SIZE,
+        NODE_SIZE,
+        node_size
+    );
+    check_nodes::<Tree>(data, node_size)?;
+
+    let leaves = data
+        .par_chunks(node_size)
+        .map(|node| {
+            let mut leaf = [0u8; NODE_SIZE];
+            leaf[..node_size].copy_from_slice(node);
+            <Tree::Hasher as Hasher>::Domain::try_from_bytes(&leaf)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    tree_from_leaves(config, leaves)
+}
+
+/// Builds a tree whose leaves are computed by `hash_leaf(index, node)` for every `node_size`
+/// chunk of `data`, e.g. to offload leaf hashing to dedicated hardware. Interior nodes are
+/// hashed with the hasher of the tree as usual.
+pub fn create_merkle_tree_with_leaf_hasher<Tree, F>(
+    config: Option<StoreConfig>,
+    data: &[u8],
+    node_size: usize,
+    hash_leaf: F,
+) -> Result<Tree>
+where
+    Tree: MerkleTreeTrait,
+    F: Fn(usize, &[u8]) -> <Tree::Hasher as Hasher>::Domain + Sync,
+{
+    check_nodes::<Tree>(data, node_size)?;
+
+    let leaves = data
+        .par_chunks(node_size)
+        .enumerate()
+        .map(|(index, node)| hash_leaf(index, node))
+        .collect::<Vec<_>>();
+
+    tree_from_leaves(config, leaves)
+}
+
+/// Builds a tree directly over already hashed leaves, e.g. the outputs of a previous layer, so
+/// that no leaf is hashed again.
+///
+/// Fails if `leaves` does not yield exactly `expected_len` leaves.
+pub fn create_merkle_tree_from_leaves<Tree, I>(
+    config: Option<StoreConfig>,
+    mut leaves: I,
+    expected_len: usize,
+) -> Result<Tree>
+where
+    Tree: MerkleTreeTrait,
+    I: Iterator<Item = <Tree::Hasher as Hasher>::Domain>,
+{
+    ensure!(
+        expected_len > 1,
+        "a merkle tree needs at least 2 leaves, got {}",
+        expected_len
+    );
+    ensure!(
+        is_merkle_tree_size_valid(expected_len, Tree::Arity::to_usize()),
+        "Invalid merkle tree size given the arity"
+    );
+
+    let mut collected = Vec::with_capacity(expected_len);
+    collected.extend(leaves.by_ref().take(expected_len));
+    ensure!(
+        collected.len() == expected_len,
+        "expected {} leaves, got {}",
+        expected_len,
+        collected.len()
+    );
+    ensure!(
+        leaves.next().is_none(),
+        "expected {} leaves, got more",
+        expected_len
+    );
+
+    tree_from_leaves(config, collected)
+}
+
+/// Checks that `data` is evenly divided into `node_size` byte nodes, which form a tree of at least
+/// 2 leaves.
+fn check_nodes<Tree: MerkleTreeTrait>(data: &[u8], node_size: usize) -> Result<()> {
+    ensure!(node_size > 0, "node_size must be greater than zero");
+    ensure!(
+        data.len() % node_size == 0,
+        Error::InvalidMerkleTreeArgs(data.len(), node_size, data.len() / node_size)
+    );
+    let size = data.len() / node_size;
+    ensure!(
+        size > 1,
+        "a merkle tree needs at least 2 leaves, got {}",
+        size
+    );
+    ensure!(
+        is_merkle_tree_size_valid(size, Tree::Arity::to_usize()),
+        "Invalid merkle tree size given the arity"
+    );
+
+    Ok(())
+}
+
+/// Builds a tree over the given leaves, in memory or with the given config.
+fn tree_from_leaves<Tree: MerkleTreeTrait>(
+    config: Option<StoreConfig>,
+    leaves: Vec<<Tree::Hasher as Hasher>::Domain>,
+) -> Result<Tree> {
+    let tree = match config {
+        Some(x) => MerkleTree::<
+            <Tree::Hasher as Hasher>::Domain,
+            <Tree::Hasher as Hasher>::Function,
+            Tree::Store,
+            Tree::Arity,
+            Tree::SubTreeArity,
+            Tree::TopTreeArity,
+        >::from_par_iter_with_config(leaves, x),
+        None => MerkleTree::<
+            <Tree::Hasher as Hasher>::Domain,
+            <Tree::Hasher as Hasher>::Function,
+            Tree::Store,
+            Tree::Arity,
+            Tree::SubTreeArity,
+            Tree::TopTreeArity,
+        >::from_par_iter(leaves),
+    }?;
+
+    Ok(Tree::from_merkle(tree))
+}
+
+/// Computes the leaf of a hybrid tree: `data` is hashed with `LeafHasher` and the digest is
+/// reinterpreted as a domain element of the tree hasher `H`.
+pub fn hybrid_leaf<LeafHasher: Hasher, H: Hasher>(data: &[u8]) -> Result<H::Domain> {
+    let digest = <LeafHasher::Function as HashFunction<LeafHasher::Domain>>::hash(data);
+    H::Domain::try_from_bytes(&digest.into_bytes())
+}
+
+/// Builds a tree whose leaves are the `LeafHasher` digests of each `node_size` chunk of `data`
+/// (see `hybrid_leaf`), while all interior nodes are hashed with the hasher of the tree.
+///
+/// This allows hashing the many leaves with a fast hasher, while the levels checked in circuits
+/// use a circuit friendly one. Proofs are validated against the data with
+/// `MerkleProofTrait::validate_hybrid_leaf`.
+pub fn create_hybrid_merkle_tree<LeafHasher: Hasher, Tree: MerkleTreeTrait>(
+    config: Option<StoreConfig>,
+    data: &[u8],
+    node_size: usize,
+) -> Result<Tree> {
+    check_nodes::<Tree>(data, node_size)?;
+
+    let leaves = data
+        .par_chunks(node_size)
+        .map(hybrid_leaf::<LeafHasher, Tree::Hasher>)
+        .collect::<Result<Vec<_>>>()?;
+
+    tree_from_leaves(config, leaves)
+}
+
+/// Checks that `tree` commits to `data`, by recomputing only the root from `data` and comparing
+/// it to the root of `tree`. Returns `false` on mismatch.
+///
+/// The base trees are folded one after the other, so only the leaves of a single base tree and
+/// the row above them are held in memory at a time, no tree is built.
+pub fn verify_tree_matches_data<Tree: MerkleTreeTrait>(tree: &Tree, data: &[u8]) -> Result<bool> {
+    let size = data.len() / NODE_SIZE;
+    ensure!(
+        data.len() == NODE_SIZE * size,
+        Error::InvalidMerkleTreeArgs(data.len(), NODE_SIZE, size)
+    );
+    if size != tree.leaves() {
+        return Ok(false);
+    }
+
+    let base_tree_count = get_base_tree_count::<Tree>();
+    let base_tree_leafs = size / base_tree_count;
+    ensure!(
+        base_tree_leafs * base_tree_count == size
+            && is_merkle_tree_size_valid(base_tree_leafs, Tree::Arity::to_usize()),
+        "Invalid merkle tree size given the arity"
+    );
+
+    let base_roots = data
+        .chunks(base_tree_leafs * NODE_SIZE)
+        .map(|base_data| {
+            let leaves = base_data
+                .chunks(NODE_SIZE)
+                .map(<Tree::Hasher as Hasher>::Domain::try_from_bytes)
+                .collect::<Result<Vec<_>>>()?;
+            Ok(fold_rows::<Tree::Hasher>(leaves, Tree::Arity::to_usize()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Combine the base tree roots through the sub and top tree levels.
+    let mut height = base_roots[0].1;
+    let mut row = base_roots
+        .into_iter()
+        .map(|(root, _)| root)
+        .collect::<Vec<_>>();
+    for &arity in &[
+        Tree::SubTreeArity::to_usize(),
+        Tree::TopTreeArity::to_usize(),
+    ] {
+        if arity > 0 {
+            row = row
+                .chunks(arity)
+                .map(|children| hash_inner::<Tree::Hasher>(children, height))
+                .collect();
+            height += 1;
+        }
+    }
+    ensure!(row.len() == 1, "Invalid merkle tree size given the arity");
+
+    Ok(row[0] == tree.root())
+}
+
+/// Hashes `row` level by level with the given arity, returning the root and its height.
+fn fold_rows<H: Hasher>(mut row: Vec<H::Domain>, arity: usize) -> (H::Domain, usize) {
+    let mut height = 0;
+    while row.len() > 1 {
+        row = row
+            .par_chunks(arity)
+            .map(|children| hash_inner::<H>(children, height))
+            .collect();
+        height += 1;
+    }
+
+    (row[0], height)
+}
+
 /// Construct a new level cache merkle tree, given the specified
 /// config.
 ///
@@ -260,7 +753,7 @@ pub fn create_base_lcmerkle_tree<H: Hasher, BaseTreeArity: 'static + PoseidonAri
     );
 
     let f = |i| {
-        let d = data_at_node(data, i)?;
+        let d = data_at_node_ref(data, i, NODE_SIZE)?;
         H::Domain::try_from_bytes(d)
     };
 
@@ -523,3 +1016,507 @@ where
         generate_base_tree::<R, Tree>(rng, nodes, temp_path)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use blstrs::Scalar as Fr;
+    use generic_array::typenum::{U2, U4, U8};
+    use rand::{thread_rng, RngCore};
+
+    use crate::merkle::{BinarySubMerkleTree, MerkleProofTrait};
+
+    #[test]
+    fn test_recommended_chunk_size() {
+        assert!(recommended_chunk_size(0) > 0);
+        assert!(recommended_chunk_size(1 << 20) > 0);
+
+        for &threads in &[1, 2, 8, 64] {
+            for &leaf_count in &[0, 1, 8, 1024, 1 << 20, 1 << 30] {
+                let chunk_size = chunk_size_for_threads(leaf_count, threads);
+                assert!(chunk_size > 0);
+                assert!(chunk_size <= leaf_count.max(1));
+
+                // Small trees are hashed in one task, large ones keep every thread busy.
+                let chunks = (leaf_count + chunk_size - 1) / chunk_size;
+                if leaf_count <= MIN_CHUNK_SIZE {
+                    assert!(chunks <= 1);
+                } else if leaf_count >= MIN_CHUNK_SIZE * threads * CHUNKS_PER_THREAD {
+                    assert!(chunks >= threads * CHUNKS_PER_THREAD);
+                }
+            }
+
+            // More threads never increase the chunk size.
+            assert!(
+                chunk_size_for_threads(1 << 30, threads * 2)
+                    <= chunk_size_for_threads(1 << 30, threads)
+            );
+        }
+    }
+
+    #[test]
+    fn test_create_base_merkle_tree_matches_sequential() {
+        // Large enough to be hashed in more than one parallel task.
+        let nodes = 4 * MIN_CHUNK_SIZE;
+        let mut rng = thread_rng();
+        let (data, _) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, nodes, None);
+
+        let tree = create_base_merkle_tree::<BinaryMerkleTree<PoseidonHasher>>(None, nodes, &data)
+            .expect("create_base_merkle_tree failure");
+
+        let leaves = data
+            .chunks(NODE_SIZE)
+            .map(<PoseidonHasher as Hasher>::Domain::try_from_bytes)
+            .collect::<Result<Vec<_>>>()
+            .expect("invalid leaf");
+        let sequential =
+            BinaryMerkleTree::<PoseidonHasher>::new(leaves).expect("failed to build tree");
+        assert_eq!(tree.root(), sequential.root());
+    }
+
+    #[test]
+    fn test_create_base_merkle_tree_from_reader() {
+        let nodes = 64;
+        let mut rng = thread_rng();
+        let (data, tree) =
+            generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, nodes, None);
+
+        let streamed = create_base_merkle_tree_from_reader::<BinaryMerkleTree<PoseidonHasher>, _>(
+            None,
+            nodes,
+            std::io::Cursor::new(&data),
+        )
+        .expect("create_base_merkle_tree_from_reader failure");
+        assert_eq!(streamed.root(), tree.root());
+
+        // Trailing data is ignored, missing data is an error.
+        let mut longer = data.clone();
+        longer.extend_from_slice(&data[..NODE_SIZE]);
+        let streamed = create_base_merkle_tree_from_reader::<BinaryMerkleTree<PoseidonHasher>, _>(
+            None,
+            nodes,
+            &longer[..],
+        )
+        .expect("create_base_merkle_tree_from_reader failure");
+        assert_eq!(streamed.root(), tree.root());
+
+        assert!(
+            create_base_merkle_tree_from_reader::<BinaryMerkleTree<PoseidonHasher>, _>(
+                None,
+                nodes,
+                &data[..data.len() - 1],
+            )
+            .is_err()
+        );
+
+        // With a config, the store only exists once the tree was fully read.
+        let dir = tempfile::tempdir().expect("tempdir failure");
+        let config = StoreConfig::new(dir.path(), "tree", default_rows_to_discard(nodes, 2));
+        let stored = create_base_merkle_tree_from_reader::<BinaryMerkleTree<PoseidonHasher>, _>(
+            Some(config.clone()),
+            nodes,
+            &data[..],
+        )
+        .expect("create_base_merkle_tree_from_reader failure");
+        assert_eq!(stored.root(), tree.root());
+        assert_eq!(
+            stored.gen_proof(7).expect("gen_proof failure").leaf(),
+            tree.gen_proof(7).expect("gen_proof failure").leaf()
+        );
+        let stored_files = || {
+            std::fs::read_dir(dir.path())
+                .expect("read_dir failure")
+                .map(|entry| entry.expect("dir entry failure").path())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            stored_files(),
+            vec![StoreConfig::data_path(&config.path, &config.id)]
+        );
+
+        let short = StoreConfig::from_config(&config, "short", None);
+        assert!(
+            create_base_merkle_tree_from_reader::<BinaryMerkleTree<PoseidonHasher>, _>(
+                Some(short),
+                nodes,
+                &data[..data.len() - 1],
+            )
+            .is_err()
+        );
+        assert_eq!(stored_files().len(), 1);
+    }
+
+    #[test]
+    fn test_create_base_merkle_tree_root() {
+        let nodes = 64;
+        let mut rng = thread_rng();
+        let (data, tree) =
+            generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, nodes, None);
+
+        let poseidon = create_base_merkle_tree_root(HashKind::Poseidon, nodes, &data)
+            .expect("create_base_merkle_tree_root failure");
+        assert_eq!(poseidon, tree.root().into_bytes());
+
+        let sha256 = create_base_merkle_tree_root(HashKind::Sha256, nodes, &data)
+            .expect("create_base_merkle_tree_root failure");
+        let sha256_tree =
+            create_base_merkle_tree::<BinaryMerkleTree<Sha256Hasher>>(None, nodes, &data)
+                .expect("create_base_merkle_tree failure");
+        assert_eq!(sha256, sha256_tree.root().into_bytes());
+
+        let blake2s = create_base_merkle_tree_root(HashKind::Blake2s, nodes, &data)
+            .expect("create_base_merkle_tree_root failure");
+        assert_ne!(poseidon, sha256);
+        assert_ne!(sha256, blake2s);
+        assert_ne!(poseidon, blake2s);
+
+        assert!(create_base_merkle_tree_root(HashKind::Sha256, nodes, &data[1..]).is_err());
+    }
+
+    fn tree_with_node_size(node_size: usize) {
+        let nodes = 64;
+        let mut rng = thread_rng();
+        let mut data = vec![0u8; nodes * node_size];
+        rng.fill_bytes(&mut data);
+
+        let tree = create_base_merkle_tree_with_node_size::<BinaryMerkleTree<PoseidonHasher>>(
+            None, &data, node_size,
+        )
+        .expect("create_base_merkle_tree_with_node_size failure");
+
+        for (i, node) in data.chunks(node_size).enumerate() {
+            let mut leaf = [0u8; NODE_SIZE];
+            leaf[..node_size].copy_from_slice(node);
+
+            let proof = tree.gen_proof(i).expect("gen_proof failure");
+            assert!(proof.validate(i));
+            assert_eq!(proof.leaf().into_bytes(), leaf.to_vec());
+        }
+
+        assert!(
+            create_base_merkle_tree_with_node_size::<BinaryMerkleTree<PoseidonHasher>>(
+                None,
+                &data[1..],
+                node_size,
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_create_base_merkle_tree_with_node_size() {
+        tree_with_node_size(8);
+        tree_with_node_size(31);
+
+        // Full nodes build the same tree as `create_base_merkle_tree`.
+        let nodes = 64;
+        let mut rng = thread_rng();
+        let (data, tree) =
+            generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, nodes, None);
+        let sized = create_base_merkle_tree_with_node_size::<BinaryMerkleTree<PoseidonHasher>>(
+            None, &data, NODE_SIZE,
+        )
+        .expect("create_base_merkle_tree_with_node_size failure");
+        assert_eq!(sized.root(), tree.root());
+
+        for &node_size in &[0, MIN_NODE_SIZE - 1, NODE_SIZE + 1, 64] {
+            assert!(
+                create_base_merkle_tree_with_node_size::<BinaryMerkleTree<PoseidonHasher>>(
+                    None,
+                    &vec![0u8; nodes * node_size],
+                    node_size,
+                )
+                .is_err()
+            );
+        }
+    }
+
+    fn padded_tree<U: 'static + PoseidonArity>(padded_leaves: usize) {
+        let size = 10;
+        let mut rng = thread_rng();
+        let (data, _) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 16, None);
+        let data = &data[..size * NODE_SIZE];
+
+        let tree =
+            create_padded_base_merkle_tree::<DiskTree<PoseidonHasher, U, U0, U0>>(None, size, data)
+                .expect("create_padded_base_merkle_tree failure");
+        assert_eq!(tree.leaves(), padded_leaves);
+
+        for i in 0..padded_leaves {
+            let proof = tree.gen_proof(i).expect("gen_proof failure");
+            assert!(proof.validate(i));
+            assert_eq!(proof.validate_committed(i, &tree.root(), size), i < size);
+            if i < size {
+                assert!(proof.validate_data(
+                    <PoseidonHasher as Hasher>::Domain::try_from_bytes(
+                        &data[i * NODE_SIZE..(i + 1) * NODE_SIZE]
+                    )
+                    .expect("try_from_bytes failure")
+                ));
+                assert!(proof.validate_non_zero(i, size));
+            } else {
+                assert!(proof.leaf_is_zero());
+            }
+        }
+    }
+
+    #[test]
+    fn test_create_base_merkle_tree_single_leaf() {
+        let data = [0u8; NODE_SIZE];
+        assert!(
+            create_base_merkle_tree::<BinaryMerkleTree<PoseidonHasher>>(None, 1, &data).is_err()
+        );
+        assert!(create_base_merkle_tree::<BinaryMerkleTree<PoseidonHasher>>(None, 0, &[]).is_err());
+        assert!(
+            create_base_merkle_tree_with_node_size::<BinaryMerkleTree<PoseidonHasher>>(
+                None, &data, NODE_SIZE
+            )
+            .is_err()
+        );
+        assert!(
+            create_base_merkle_tree_chunked::<BinaryMerkleTree<PoseidonHasher>>(None, 1, &data, 1)
+                .is_err()
+        );
+        assert!(
+            create_base_merkle_tree_from_reader::<BinaryMerkleTree<PoseidonHasher>, _>(
+                None,
+                1,
+                &data[..]
+            )
+            .is_err()
+        );
+
+        // Padding a single node yields a tree with a proper path.
+        let tree =
+            create_padded_base_merkle_tree::<BinaryMerkleTree<PoseidonHasher>>(None, 1, &data)
+                .expect("create_padded_base_merkle_tree failure");
+        let proof = tree.gen_proof(0).expect("gen_proof failure");
+        assert_eq!(proof.path().len(), 1);
+        assert!(proof.validate(0));
+    }
+
+    #[test]
+    fn test_create_padded_base_merkle_tree() {
+        assert_eq!(padded_leaf_count(1, 2), 2);
+        assert_eq!(padded_leaf_count(10, 2), 16);
+        assert_eq!(padded_leaf_count(16, 2), 16);
+        assert_eq!(padded_leaf_count(10, 8), 64);
+
+        padded_tree::<U2>(16);
+        padded_tree::<U4>(16);
+        padded_tree::<U8>(64);
+
+        // A valid size builds the same tree as `create_base_merkle_tree`.
+        let mut rng = thread_rng();
+        let (data, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 32, None);
+        let padded =
+            create_padded_base_merkle_tree::<BinaryMerkleTree<PoseidonHasher>>(None, 32, &data)
+                .expect("create_padded_base_merkle_tree failure");
+        assert_eq!(padded.root(), tree.root());
+
+        assert!(
+            create_padded_base_merkle_tree::<BinaryMerkleTree<PoseidonHasher>>(None, 0, &[])
+                .is_err()
+        );
+        assert!(
+            create_padded_base_merkle_tree::<BinarySubMerkleTree<PoseidonHasher>>(
+                None,
+                10,
+                &data[..10 * NODE_SIZE]
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_create_base_merkle_tree_chunked() {
+        let nodes = 64;
+        let mut rng = thread_rng();
+        let (data, _) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, nodes, None);
+
+        let tree = create_base_merkle_tree::<BinaryMerkleTree<PoseidonHasher>>(None, nodes, &data)
+            .expect("create_base_merkle_tree failure");
+
+        for &chunk_count in &[1, 2, 3, 8, 64, 100] {
+            let chunked = create_base_merkle_tree_chunked::<BinaryMerkleTree<PoseidonHasher>>(
+                None,
+                nodes,
+                &data,
+                chunk_count,
+            )
+            .expect("create_base_merkle_tree_chunked failure");
+            assert_eq!(chunked.root(), tree.root(), "chunk_count {}", chunk_count);
+        }
+
+        assert!(
+            create_base_merkle_tree_chunked::<BinaryMerkleTree<PoseidonHasher>>(
+                None, nodes, &data, 0
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_create_hybrid_merkle_tree() {
+        let nodes = 16;
+        let node_size = 64;
+        let mut rng = thread_rng();
+        let mut data = vec![0u8; nodes * node_size];
+        rng.fill_bytes(&mut data);
+
+        let tree = create_hybrid_merkle_tree::<Sha256Hasher, BinaryMerkleTree<PoseidonHasher>>(
+            None, &data, node_size,
+        )
+        .expect("create_hybrid_merkle_tree failure");
+        assert_eq!(tree.leaves(), nodes);
+
+        for (i, chunk) in data.chunks(node_size).enumerate() {
+            let proof = tree.gen_proof(i).expect("gen_proof failure");
+            assert!(proof.verify());
+            assert!(proof.validate_hybrid_leaf::<Sha256Hasher>(i, chunk));
+            assert!(!proof.validate_hybrid_leaf::<Sha256Hasher>(i, &chunk[1..]));
+            assert!(!proof.validate_hybrid_leaf::<Sha256Hasher>((i + 1) % nodes, chunk));
+        }
+
+        assert!(
+            create_hybrid_merkle_tree::<Sha256Hasher, BinaryMerkleTree<PoseidonHasher>>(
+                None,
+                &data[1..],
+                node_size,
+            )
+            .is_err()
+        );
+    }
+
+    fn tree_matches_data<Tree: 'static + MerkleTreeTrait>() {
+        let nodes = 64 * get_base_tree_count::<Tree>();
+        let mut rng = thread_rng();
+        let (mut data, tree) = generate_tree::<Tree, _>(&mut rng, nodes, None);
+
+        assert!(verify_tree_matches_data(&tree, &data).expect("verify failure"));
+
+        // Flip the lowest bit of the last node, keeping it a valid field element.
+        let offset = (nodes - 1) * NODE_SIZE;
+        data[offset] ^= 1;
+        assert!(!verify_tree_matches_data(&tree, &data).expect("verify failure"));
+
+        assert!(
+            !verify_tree_matches_data(&tree, &data[..NODE_SIZE * nodes / 2])
+                .expect("verify failure")
+        );
+        assert!(verify_tree_matches_data(&tree, &data[1..]).is_err());
+    }
+
+    #[test]
+    fn test_verify_tree_matches_data_binary() {
+        tree_matches_data::<BinaryMerkleTree<PoseidonHasher>>();
+    }
+
+    #[test]
+    fn test_verify_tree_matches_data_oct_2() {
+        tree_matches_data::<
+            MerkleTreeWrapper<
+                PoseidonHasher,
+                DiskStore<<PoseidonHasher as Hasher>::Domain>,
+                U8,
+                U2,
+                U0,
+            >,
+        >();
+    }
+
+    #[test]
+    fn test_verify_tree_matches_data_oct_4_2() {
+        tree_matches_data::<
+            MerkleTreeWrapper<
+                PoseidonHasher,
+                DiskStore<<PoseidonHasher as Hasher>::Domain>,
+                U8,
+                U4,
+                U2,
+            >,
+        >();
+    }
+
+    #[test]
+    fn test_create_merkle_tree_with_leaf_hasher() {
+        let nodes = 64;
+        let mut rng = thread_rng();
+        let (data, tree) =
+            generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, nodes, None);
+
+        // A callback mirroring the default leaves results in the same tree.
+        let mirrored = create_merkle_tree_with_leaf_hasher::<BinaryMerkleTree<PoseidonHasher>, _>(
+            None,
+            &data,
+            NODE_SIZE,
+            |_, node| {
+                <PoseidonHasher as Hasher>::Domain::try_from_bytes(node)
+                    .expect("try_from_bytes failure")
+            },
+        )
+        .expect("create_merkle_tree_with_leaf_hasher failure");
+        assert_eq!(mirrored.root(), tree.root());
+
+        // The callback receives the index of every node.
+        let indexed = create_merkle_tree_with_leaf_hasher::<BinaryMerkleTree<PoseidonHasher>, _>(
+            None,
+            &data,
+            NODE_SIZE,
+            |index, _| Fr::from(index as u64).into(),
+        )
+        .expect("create_merkle_tree_with_leaf_hasher failure");
+        let expected = BinaryMerkleTree::<PoseidonHasher>::new(
+            (0..nodes as u64).map(|index| Fr::from(index).into()),
+        )
+        .expect("failed to build tree");
+        assert_eq!(indexed.root(), expected.root());
+    }
+
+    #[test]
+    fn test_create_merkle_tree_from_leaves() {
+        let nodes = 16;
+        let node_size = 64;
+        let mut rng = thread_rng();
+        let mut data = vec![0u8; nodes * node_size];
+        rng.fill_bytes(&mut data);
+
+        let tree = create_hybrid_merkle_tree::<Sha256Hasher, BinaryMerkleTree<PoseidonHasher>>(
+            None, &data, node_size,
+        )
+        .expect("create_hybrid_merkle_tree failure");
+        let hashed = data
+            .chunks(node_size)
+            .map(|node| {
+                hybrid_leaf::<Sha256Hasher, PoseidonHasher>(node).expect("hybrid_leaf failure")
+            })
+            .collect::<Vec<_>>();
+
+        let from_leaves = create_merkle_tree_from_leaves::<BinaryMerkleTree<PoseidonHasher>, _>(
+            None,
+            hashed.iter().copied(),
+            nodes,
+        )
+        .expect("create_merkle_tree_from_leaves failure");
+        assert_eq!(from_leaves.root(), tree.root());
+
+        // Too few or too many leaves.
+        assert!(
+            create_merkle_tree_from_leaves::<BinaryMerkleTree<PoseidonHasher>, _>(
+                None,
+                hashed[1..].iter().copied(),
+                nodes,
+            )
+            .is_err()
+        );
+        assert!(
+            create_merkle_tree_from_leaves::<BinaryMerkleTree<PoseidonHasher>, _>(
+                None,
+                hashed.iter().chain(hashed.iter()).copied(),
+                nodes,
+            )
+            .is_err()
+        );
+    }
+}
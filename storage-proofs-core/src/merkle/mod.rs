@@ -8,12 +8,20 @@ use filecoin_hashers::Hasher;
 use generic_array::typenum::{U0, U2, U4, U8};
 use merkletree::store::LevelCacheStore;
 
+mod append;
+mod arena;
+mod batch;
 mod builders;
 mod proof;
+mod transcript;
 mod tree;
 
+pub use append::*;
+pub use arena::*;
+pub use batch::*;
 pub use builders::*;
 pub use proof::*;
+pub use transcript::*;
 pub use tree::*;
 
 pub type LCStore<E> = LevelCacheStore<E, File>;
@@ -1,17 +1,24 @@
 #![allow(clippy::len_without_is_empty)]
 
 use std::fmt::Debug;
+use std::io::{Read, Write};
 use std::marker::PhantomData;
 use std::slice::Iter;
 
 use anyhow::{ensure, Result};
+use bincode::Options;
 use blstrs::Scalar as Fr;
-use filecoin_hashers::{Hasher, PoseidonArity};
+use filecoin_hashers::{Domain, HashFunction, HashKind, Hasher, PoseidonArity};
 use generic_array::typenum::{Unsigned, U0};
-use merkletree::hash::Algorithm;
+use merkletree::hash::{Algorithm, Hashable};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use subtle::ConstantTimeEq;
 
-use crate::drgraph::graph_height;
+use crate::{
+    drgraph::{graph_height, read_varint, write_varint},
+    merkle::{hybrid_leaf, MerkleTreeTrait},
+    util::NODE_SIZE,
+};
 
 /// Trait to abstract over the concept of Merkle Proof.
 pub trait MerkleProofTrait: Clone + Serialize + DeserializeOwned + Debug + Sync + Send {
@@ -58,6 +65,27 @@ pub trait MerkleProofTrait: Clone + Serialize + DeserializeOwned + Debug + Sync
             .map(|v| (v.0.iter().copied().map(Into::into).collect(), v.1))
             .collect::<Vec<_>>()
     }
+
+    /// Returns the sibling hashes of all levels as one vector, starting at the leaf, and the
+    /// index of every level as another, as allocated separately by circuits.
+    ///
+    /// Level `i` has `arity - 1` siblings, with the arity of that level.
+    fn as_circuit_inputs(&self) -> (Vec<Fr>, Vec<usize>) {
+        let path = self.path();
+        let indexes = path.iter().map(|(_, index)| *index).collect();
+        let hashes = path
+            .into_iter()
+            .flat_map(|(siblings, _)| siblings.into_iter().map(Into::into))
+            .collect();
+
+        (hashes, indexes)
+    }
+
+    /// Returns the root derived by hashing up the path from the leaf, independently of the
+    /// claimed `root`.
+    fn computed_root(&self) -> <Self::Hasher as Hasher>::Domain;
+
+    /// Returns true if the claimed root is the `computed_root`.
     fn verify(&self) -> bool;
 
     /// Validates the MerkleProof and that it corresponds to the supplied node.
@@ -65,11 +93,106 @@ pub trait MerkleProofTrait: Clone + Serialize + DeserializeOwned + Debug + Sync
     /// TODO: audit performance and usage in case verification is
     /// unnecessary based on how it's used.
     fn validate(&self, node: usize) -> bool {
+        self.check(node).is_ok()
+    }
+
+    /// Like `validate`, but returns why the proof is invalid.
+    fn check(&self, node: usize) -> std::result::Result<(), ProofError> {
+        if self.path_len() == 0 {
+            return Err(ProofError::EmptyPath);
+        }
         if !self.verify() {
+            return Err(ProofError::RootMismatch);
+        }
+
+        let path_index = self.path_index();
+        if node != path_index {
+            return Err(ProofError::PathIndexMismatch {
+                expected: node,
+                got: path_index,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Like `validate`, additionally returning the length of the path.
+    ///
+    /// The length is returned whether or not the proof is valid. It is the number of hashes a
+    /// validation performs, as `check` folds every level of a non-empty path once before
+    /// comparing the index, and rejects an empty path without hashing.
+    fn validate_with_path_len(&self, node: usize) -> (bool, usize) {
+        (self.check(node).is_ok(), self.path_len())
+    }
+
+    /// Like `validate`, but returns the running hashes of the path, as returned by `level_hashes`,
+    /// if the proof is valid for `node`.
+    fn validate_with_levels(&self, node: usize) -> Option<Vec<<Self::Hasher as Hasher>::Domain>> {
+        self.check(node).ok().map(|_| self.level_hashes())
+    }
+
+    /// Like `validate`, but compares the calculated and the claimed root in constant time, so
+    /// that the time taken does not depend on how many of their bytes match.
+    ///
+    /// This is slower than `validate`, use it where proofs from adversaries are verified online.
+    fn validate_ct(&self, node: usize) -> bool {
+        let levels = self.level_hashes();
+        let calculated_root = levels.last().copied().unwrap_or_default().into_bytes();
+        let roots_equal: bool = calculated_root.ct_eq(&self.root().into_bytes()).into();
+
+        // The shape and position of the path are public, only the hashes must not leak.
+        roots_equal & (self.path_len() > 0) & (node == self.path_index())
+    }
+
+    /// Returns true if the leaf is the default (all zero) domain element.
+    ///
+    /// Zero leaves are only legitimate as padding, a stored and encoded node is never zero.
+    fn leaf_is_zero(&self) -> bool {
+        self.leaf() == <Self::Hasher as Hasher>::Domain::default()
+    }
+
+    /// Like `validate`, but also rejects a zero leaf if `node` is one of the first `data_leaves`
+    /// positions, which hold data and not padding.
+    fn validate_non_zero(&self, node: usize, data_leaves: usize) -> bool {
+        if node < data_leaves && self.leaf_is_zero() {
+            return false;
+        }
+
+        self.validate(node)
+    }
+
+    /// Validates the proof against a fully committed, padded tree: the proof must fold to
+    /// `expected_root`, correspond to `node`, and `node` must be one of the `real_leaf_count`
+    /// leaves preceding the padding.
+    fn validate_committed(
+        &self,
+        node: usize,
+        expected_root: &<Self::Hasher as Hasher>::Domain,
+        real_leaf_count: usize,
+    ) -> bool {
+        node < real_leaf_count && self.root() == *expected_root && self.validate(node)
+    }
+
+    /// Validates the proof for `node` under an outer commitment: `outer_commitment` must be the
+    /// `hash_md` of the (at least two) `outer_fields`, of which the one at `root_position` is the
+    /// root of this proof.
+    fn validate_under_commitment(
+        &self,
+        node: usize,
+        outer_fields: &[Fr],
+        root_position: usize,
+        outer_commitment: &<Self::Hasher as Hasher>::Domain,
+    ) -> bool {
+        if outer_fields.len() < 2
+            || outer_fields.get(root_position) != Some(&Into::<Fr>::into(self.root()))
+        {
             return false;
         }
 
-        node == self.path_index()
+        let fields: Vec<<Self::Hasher as Hasher>::Domain> =
+            outer_fields.iter().copied().map(Into::into).collect();
+        <Self::Hasher as Hasher>::Function::hash_md(&fields) == *outer_commitment
+            && self.validate(node)
     }
 
     fn validate_data(&self, data: <Self::Hasher as Hasher>::Domain) -> bool {
@@ -80,11 +203,113 @@ pub trait MerkleProofTrait: Clone + Serialize + DeserializeOwned + Debug + Sync
         self.leaf() == data
     }
 
+    /// Validates that the leaf of this proof is the root of a lower tree (`lower_root`) and that
+    /// it is included at position `node` of the upper tree committed to by `expected_root`.
+    fn validate_as_subroot(
+        &self,
+        node: usize,
+        lower_root: &<Self::Hasher as Hasher>::Domain,
+        expected_root: &<Self::Hasher as Hasher>::Domain,
+    ) -> bool {
+        if self.leaf() != *lower_root || self.root() != *expected_root {
+            return false;
+        }
+
+        self.validate(node)
+    }
+
+    /// Validates that the leaf of this proof is the leaf hash of the structured `record` and
+    /// that it is included at position `node` of the tree committed to by `expected_root`.
+    fn validate_record<T: Hashable<<Self::Hasher as Hasher>::Function>>(
+        &self,
+        node: usize,
+        record: &T,
+        expected_root: &<Self::Hasher as Hasher>::Domain,
+    ) -> bool {
+        let leaf = <Self::Hasher as Hasher>::Function::hash_leaf(record);
+        if self.leaf() != leaf || self.root() != *expected_root {
+            return false;
+        }
+
+        self.validate(node)
+    }
+
+    /// Validates the proof for `node` like `validate`, and that its leaf is the leaf hash of
+    /// `data`. Unlike `validate_data`, the raw data is checked rather than an already hashed
+    /// leaf.
+    fn validate_with_data<T: Hashable<<Self::Hasher as Hasher>::Function>>(
+        &self,
+        node: usize,
+        data: &T,
+    ) -> bool {
+        <Self::Hasher as Hasher>::Function::hash_leaf(data) == self.leaf() && self.validate(node)
+    }
+
+    /// Cheap pre-filter for batches: proofs from the same tree necessarily share their root.
+    ///
+    /// Equal roots are a necessary but not a sufficient condition, they only imply the same tree
+    /// in the absence of hash collisions.
+    fn same_tree(&self, other: &Self) -> bool {
+        self.root() == other.root()
+    }
+
+    /// Returns the index and depth claimed by this proof, if its root is `expected_root` and it
+    /// passes `check` for that index.
+    fn validate_and_describe(
+        &self,
+        expected_root: &<Self::Hasher as Hasher>::Domain,
+    ) -> Option<ProofInfo> {
+        if self.root() != *expected_root {
+            return None;
+        }
+
+        let index = self.path_index();
+        self.check(index).ok()?;
+
+        Some(ProofInfo {
+            index,
+            depth: self.path_len(),
+        })
+    }
+
+    /// Validates a proof of a tree built by `create_hybrid_merkle_tree`: the leaf must be the
+    /// `LeafHasher` digest of `data`, and the proof must be valid for `node`.
+    fn validate_hybrid_leaf<LeafHasher: Hasher>(&self, node: usize, data: &[u8]) -> bool {
+        match hybrid_leaf::<LeafHasher, Self::Hasher>(data) {
+            Ok(leaf) => self.leaf() == leaf && self.validate(node),
+            Err(_) => false,
+        }
+    }
+
     fn leaf(&self) -> <Self::Hasher as Hasher>::Domain;
     fn root(&self) -> <Self::Hasher as Hasher>::Domain;
     fn len(&self) -> usize;
     fn path(&self) -> Vec<(Vec<<Self::Hasher as Hasher>::Domain>, usize)>;
 
+    /// Returns the number of levels of the path, without cloning it as `path` does.
+    fn path_len(&self) -> usize;
+
+    /// Returns the running hashes obtained while folding the path: the leaf first, followed by
+    /// the hash produced at every level, the last one being the calculated root.
+    fn level_hashes(&self) -> Vec<<Self::Hasher as Hasher>::Domain>;
+
+    /// Returns how many levels of this proof reproduce the trusted running hashes
+    /// `expected_levels` (as returned by `level_hashes` for a known good proof of the same
+    /// position) before diverging. A fully matching proof returns the length of its path.
+    fn matching_prefix_len(&self, expected_levels: &[<Self::Hasher as Hasher>::Domain]) -> usize {
+        let levels = self.level_hashes();
+        if levels.first() != expected_levels.first() {
+            return 0;
+        }
+
+        levels
+            .iter()
+            .zip(expected_levels.iter())
+            .skip(1)
+            .take_while(|(level, expected)| level == expected)
+            .count()
+    }
+
     fn path_index(&self) -> usize {
         self.path()
             .iter()
@@ -100,6 +325,110 @@ pub trait MerkleProofTrait: Clone + Serialize + DeserializeOwned + Debug + Sync
     fn expected_len(&self, leaves: usize) -> usize {
         compound_path_length::<Self::Arity, Self::SubTreeArity, Self::TopTreeArity>(leaves)
     }
+
+    /// Returns true if the path has exactly the levels of a tree with `leaf_count` leaves, e.g.
+    /// it was not padded with filler levels.
+    fn is_minimal(&self, leaf_count: usize) -> bool {
+        self.path().len() == self.expected_len(leaf_count)
+    }
+
+    /// Checks that the path of this proof is well formed for a tree with `leaves` leaves: it must
+    /// have the expected length, every index must be smaller than the arity of its level and the
+    /// addressed leaf must be within the tree.
+    ///
+    /// No hashes are checked, this is meant to be combined with `validate`.
+    fn directions_valid(&self, leaves: usize) -> bool {
+        let path = self.path();
+        if leaves == 0 || path.len() != self.expected_len(leaves) {
+            return false;
+        }
+
+        let mut arities = Vec::with_capacity(path.len());
+        let sub_arity = Self::SubTreeArity::to_usize();
+        let top_arity = Self::TopTreeArity::to_usize();
+        let compound_levels = (sub_arity > 0) as usize + (top_arity > 0) as usize;
+        arities.resize(path.len() - compound_levels, Self::Arity::to_usize());
+        if sub_arity > 0 {
+            arities.push(sub_arity);
+        }
+        if top_arity > 0 {
+            arities.push(top_arity);
+        }
+
+        let indexes_valid = path
+            .iter()
+            .zip(arities.iter())
+            .all(|((_, index), arity)| index < arity);
+
+        indexes_valid && self.path_index() < leaves
+    }
+
+    /// Like `validate`, but also rejects proofs whose path is not shaped exactly like a path of
+    /// a tree with `tree_size` leaves, see `directions_valid`.
+    ///
+    /// Extra levels can be folded into any root a prover chooses, so proofs received from
+    /// untrusted provers must be checked against the size of the committed tree.
+    fn validate_for_tree(&self, node: usize, tree_size: usize) -> bool {
+        self.directions_valid(tree_size) && self.validate(node)
+    }
+}
+
+/// The reasons a proof is invalid, as returned by `MerkleProofTrait::check`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum ProofError {
+    #[error("the path of the proof is empty")]
+    EmptyPath,
+    #[error("the path does not hash to the root of the proof")]
+    RootMismatch,
+    #[error("the path proves node {got}, expected node {expected}")]
+    PathIndexMismatch { expected: usize, got: usize },
+}
+
+/// A proof together with the kind of hash its tree was built with. The tag is serialized with
+/// the proof, so a proof of a tree of another hash is rejected after deserialization.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaggedProof<P> {
+    kind: HashKind,
+    proof: P,
+}
+
+impl<P: MerkleProofTrait> TaggedProof<P> {
+    /// Tags `proof` with the kind of its hasher.
+    pub fn new(proof: P) -> Self {
+        TaggedProof {
+            kind: <P::Hasher as Hasher>::KIND,
+            proof,
+        }
+    }
+
+    pub fn kind(&self) -> HashKind {
+        self.kind
+    }
+
+    pub fn proof(&self) -> &P {
+        &self.proof
+    }
+
+    /// Validates the proof for `node` against `expected_root`, only if it is tagged with the
+    /// kind of hash `P` is folded with.
+    pub fn validate_tagged(
+        &self,
+        node: usize,
+        expected_root: &<P::Hasher as Hasher>::Domain,
+    ) -> bool {
+        self.kind == <P::Hasher as Hasher>::KIND
+            && self.proof.root() == *expected_root
+            && self.proof.validate(node)
+    }
+}
+
+/// Metadata of a proof, see `MerkleProofTrait::validate_and_describe`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProofInfo {
+    /// The index of the proven leaf.
+    pub index: usize,
+    /// The number of levels of the path.
+    pub depth: usize,
 }
 
 pub fn base_path_length<A: Unsigned, B: Unsigned, C: Unsigned>(leaves: usize) -> usize {
@@ -126,6 +455,28 @@ pub fn compound_path_length<A: Unsigned, B: Unsigned, C: Unsigned>(leaves: usize
 
     len
 }
+
+/// Returns the exact length of the `MerkleProof::to_bytes` encoding of any proof of a tree with
+/// `graph_size` leaves, for domain elements of `hash_len` bytes.
+///
+/// This allows budgeting for proofs before generating them.
+pub fn expected_proof_size<A: Unsigned, B: Unsigned, C: Unsigned>(
+    graph_size: usize,
+    hash_len: usize,
+) -> usize {
+    let level_len = |arity: usize| (arity - 1) * hash_len + 1;
+
+    let mut size = base_path_length::<A, B, C>(graph_size) * level_len(A::to_usize());
+    for &arity in &[B::to_usize(), C::to_usize()] {
+        if arity > 0 {
+            size += level_len(arity);
+        }
+    }
+
+    // The leaf and the root.
+    size + 2 * hash_len
+}
+
 pub fn compound_tree_height<A: Unsigned, B: Unsigned, C: Unsigned>(leaves: usize) -> usize {
     // base layer
     let a = graph_height::<A>(leaves) - 1;
@@ -147,6 +498,104 @@ pub fn compound_tree_height<A: Unsigned, B: Unsigned, C: Unsigned>(leaves: usize
     a + b + c
 }
 
+/// Hashes the `children` of an interior node, exactly like the verification of an inclusion
+/// path does. `height` is the row being combined, starting at `0` for the leaves.
+///
+/// This allows assembling trees manually which are consistent with proof verification.
+pub fn hash_inner<H: Hasher>(children: &[H::Domain], height: usize) -> H::Domain {
+    let mut a = H::Function::default();
+    a.multi_node(children, height)
+}
+
+/// Natively validates a path given as field elements, as returned by
+/// `MerkleProofTrait::as_pairs`, so the inputs of a circuit can be cross checked.
+pub fn validate_from_frs<H: Hasher>(
+    leaf: Fr,
+    path: &[(Vec<Fr>, usize)],
+    expected_root: Fr,
+) -> bool {
+    let mut node: H::Domain = leaf.into();
+    for (height, (siblings, index)) in path.iter().enumerate() {
+        if *index > siblings.len() {
+            return false;
+        }
+
+        let mut children: Vec<H::Domain> = siblings.iter().copied().map(Into::into).collect();
+        children.insert(*index, node);
+        node = hash_inner::<H>(&children, height);
+    }
+
+    Into::<Fr>::into(node) == expected_root
+}
+
+/// Returns one single level proof per child of the root of `tree`, in order. Each proves the
+/// child at its position, with the other children as siblings, so every child can be checked
+/// against the root independently.
+///
+/// Only base trees are supported. Fails for a tree with a single leaf, as its root has no
+/// children.
+pub fn top_level_proofs<Tree: MerkleTreeTrait<SubTreeArity = U0, TopTreeArity = U0>>(
+    tree: &Tree,
+) -> Result<Vec<MerkleProof<Tree::Hasher, Tree::Arity>>> {
+    ensure!(tree.leaves() > 1, "a single leaf tree has no top level");
+
+    // The path of the first leaf passes through the first child of the root.
+    let proof = tree.gen_proof(0)?;
+    let levels = proof.level_hashes();
+    let (mut children, index) = proof.path().pop().expect("path is not empty");
+    children.insert(index, levels[levels.len() - 2]);
+
+    let root = tree.root();
+    let proofs = (0..children.len())
+        .map(|i| {
+            let mut hashes = children.clone();
+            let leaf = hashes.remove(i);
+            let path = vec![PathElement {
+                hashes,
+                index: i,
+                _arity: PhantomData,
+            }];
+            MerkleProof {
+                data: ProofData::Single(SingleProof::new(path.into(), root, leaf)),
+            }
+        })
+        .collect();
+
+    Ok(proofs)
+}
+
+/// Returns a structurally valid proof over random hashes, with `depth` levels of `Arity`, and
+/// the node it proves, for property testing the verification without building a tree.
+///
+/// Panics if `depth` is zero. Available to other crates with the `test-util` feature.
+#[cfg(any(test, feature = "test-util"))]
+pub fn arbitrary_proof<H: Hasher, Arity: PoseidonArity, R: rand::Rng>(
+    rng: &mut R,
+    depth: usize,
+) -> (MerkleProof<H, Arity>, usize) {
+    assert!(depth > 0, "a proof needs at least one level");
+
+    let path: InclusionPath<H, Arity> = (0..depth)
+        .map(|_| PathElement {
+            hashes: (1..Arity::to_usize())
+                .map(|_| H::Domain::random(&mut *rng))
+                .collect(),
+            index: rng.gen_range(0..Arity::to_usize()),
+            _arity: PhantomData,
+        })
+        .collect::<Vec<_>>()
+        .into();
+    let leaf = H::Domain::random(rng);
+    let root = path.root(leaf);
+
+    let proof = MerkleProof {
+        data: ProofData::Single(SingleProof::new(path, root, leaf)),
+    };
+    let node = proof.path_index();
+
+    (proof, node)
+}
+
 macro_rules! forward_method {
     ($caller:expr, $name:ident) => {
         match $caller {
@@ -194,6 +643,23 @@ impl<H: Hasher, Arity: PoseidonArity> InclusionPath<H, Arity> {
         })
     }
 
+    /// Calculate the running hashes of this path, given the leaf as input. The first element is
+    /// the leaf and the last one the root.
+    pub fn levels(&self, leaf: H::Domain) -> Vec<H::Domain> {
+        let mut a = H::Function::default();
+        let mut levels = Vec::with_capacity(self.path.len() + 1);
+        levels.push(leaf);
+        for (height, element) in self.path.iter().enumerate() {
+            a.reset();
+
+            let mut nodes = element.hashes.clone();
+            nodes.insert(element.index, levels[height]);
+
+            levels.push(a.multi_node(&nodes, height));
+        }
+        levels
+    }
+
     pub fn len(&self) -> usize {
         self.path.len()
     }
@@ -241,6 +707,337 @@ pub struct MerkleProof<
     data: ProofData<H, BaseArity, SubTreeArity, TopTreeArity>,
 }
 
+impl<
+        H: Hasher,
+        Arity: 'static + PoseidonArity,
+        SubTreeArity: 'static + PoseidonArity,
+        TopTreeArity: 'static + PoseidonArity,
+    > MerkleProof<H, Arity, SubTreeArity, TopTreeArity>
+{
+    /// Returns the leaf this proof addresses, recovered from the indexes of its path.
+    ///
+    /// The path starts at the leaf, so the first index is the least significant digit. Each
+    /// index is in the radix of its level: `Arity` along the base tree, then `SubTreeArity` and
+    /// `TopTreeArity` for the levels of compound trees.
+    pub fn challenged_node(&self) -> usize {
+        self.path_index()
+    }
+
+    /// Deserializes a proof from its `bincode` encoding, as produced by `bincode::serialize`.
+    ///
+    /// Fails if any bytes remain after the proof, or if the path does not have `path_len` levels.
+    pub fn parse_exact(bytes: &[u8], path_len: usize) -> Result<Self> {
+        let proof: Self = exact_bincode().deserialize(bytes)?;
+        ensure!(
+            proof.path().len() == path_len,
+            "expected a path of length {}, got {}",
+            path_len,
+            proof.path().len()
+        );
+
+        Ok(proof)
+    }
+
+    /// Writes the proof as a frame: its `bincode` encoding, prefixed by the encoding length as
+    /// a little endian `u32`.
+    pub fn write_framed<W: Write>(&self, w: &mut W) -> Result<()> {
+        let body = bincode::serialize(self)?;
+        ensure!(
+            body.len() <= u32::MAX as usize,
+            "proof is too large to frame"
+        );
+
+        w.write_all(&(body.len() as u32).to_le_bytes())?;
+        w.write_all(&body)?;
+        Ok(())
+    }
+
+    /// Reads a proof frame written by `write_framed`.
+    ///
+    /// The length prefix is untrusted, so the body is only allocated as it is read.
+    pub fn read_framed<R: Read>(r: &mut R) -> Result<Self> {
+        let mut len = [0u8; 4];
+        r.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len) as usize;
+
+        let mut body = Vec::new();
+        r.take(len as u64).read_to_end(&mut body)?;
+        ensure!(
+            body.len() == len,
+            "proof frame of {} bytes is truncated to {} bytes",
+            len,
+            body.len()
+        );
+
+        Ok(exact_bincode().deserialize(&body)?)
+    }
+
+    /// Encodes the proof as the sibling hashes of every level of the path, each level followed by
+    /// a byte holding the index of the path within it, and finally the leaf and the root.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write_to(&mut bytes)
+            .expect("writing to a Vec cannot fail");
+
+        bytes
+    }
+
+    /// Writes the encoding of `to_bytes` to `w`, without allocating it first.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        for (siblings, index) in self.path() {
+            for sibling in siblings {
+                w.write_all(&sibling.into_bytes())?;
+            }
+            w.write_all(&[index as u8])?;
+        }
+        w.write_all(&self.leaf().into_bytes())?;
+        w.write_all(&self.root().into_bytes())?;
+
+        Ok(())
+    }
+
+    /// Decodes a proof encoded by `to_bytes`.
+    ///
+    /// Fails if the length is not the one of a whole proof, or if an index is out of range.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let level_len = |arity: usize| (arity - 1) * NODE_SIZE + 1;
+        let (sub_arity, top_arity) = (SubTreeArity::to_usize(), TopTreeArity::to_usize());
+        ensure!(
+            sub_arity > 0 || top_arity == 0,
+            "a top tree requires a sub tree"
+        );
+
+        // The sub and top tree each add a single level on top of the base tree path.
+        let fixed_len = [sub_arity, top_arity]
+            .iter()
+            .filter(|&&arity| arity > 0)
+            .map(|&arity| level_len(arity))
+            .sum::<usize>()
+            + 2 * NODE_SIZE;
+        let base_level_len = level_len(Arity::to_usize());
+        ensure!(
+            bytes.len() >= fixed_len && (bytes.len() - fixed_len) % base_level_len == 0,
+            "invalid proof length {}",
+            bytes.len()
+        );
+
+        let mut rest = bytes;
+        let base_proof = read_path(&mut rest, (bytes.len() - fixed_len) / base_level_len)?;
+        let data = if top_arity > 0 {
+            let sub_proof = read_path(&mut rest, 1)?;
+            let top_proof = read_path(&mut rest, 1)?;
+            let (leaf, root) = read_leaf_and_root::<H>(rest)?;
+            ProofData::Top(TopProof::new(base_proof, sub_proof, top_proof, root, leaf))
+        } else if sub_arity > 0 {
+            let sub_proof = read_path(&mut rest, 1)?;
+            let (leaf, root) = read_leaf_and_root::<H>(rest)?;
+            ProofData::Sub(SubProof::new(base_proof, sub_proof, root, leaf))
+        } else {
+            let (leaf, root) = read_leaf_and_root::<H>(rest)?;
+            ProofData::Single(SingleProof::new(base_proof, root, leaf))
+        };
+
+        Ok(MerkleProof { data })
+    }
+
+    /// Encodes the proof like `to_bytes`, but without the root, which the verifier usually
+    /// already knows as the commitment. The encoding is `NODE_SIZE` bytes shorter.
+    pub fn to_compact_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.to_bytes();
+        bytes.truncate(bytes.len() - NODE_SIZE);
+        bytes
+    }
+
+    /// Decodes a proof encoded by `to_compact_bytes`, with `expected_root` as its root.
+    ///
+    /// The path is not checked against `expected_root`, use `validate` for that.
+    pub fn from_compact_bytes(bytes: &[u8], expected_root: &H::Domain) -> Result<Self> {
+        let mut full = Vec::with_capacity(bytes.len() + NODE_SIZE);
+        full.extend_from_slice(bytes);
+        full.extend(expected_root.into_bytes());
+
+        Self::from_bytes(&full)
+    }
+
+    /// Encodes the proof with its indexes bit packed, `ceil(log2(arity))` bits per level.
+    ///
+    /// The number of levels comes first as a varint, followed by the siblings of all levels, the
+    /// leaf and the root. The indexes of all levels follow, starting at the least significant
+    /// bit of the first trailing byte.
+    pub fn to_packed_bytes(&self) -> Vec<u8> {
+        let path = self.path();
+
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, path.len() as u32);
+        for (siblings, _) in &path {
+            for sibling in siblings {
+                bytes.extend(sibling.into_bytes());
+            }
+        }
+        bytes.extend(self.leaf().into_bytes());
+        bytes.extend(self.root().into_bytes());
+
+        let index_bits = path
+            .iter()
+            .map(|(siblings, _)| index_bits(siblings.len() + 1))
+            .sum::<usize>();
+        let mut trailer = vec![0u8; (index_bits + 7) / 8];
+        let mut offset = 0;
+        for (siblings, index) in &path {
+            for bit in 0..index_bits(siblings.len() + 1) {
+                if (index >> bit) & 1 == 1 {
+                    trailer[(offset + bit) / 8] |= 1 << ((offset + bit) % 8);
+                }
+            }
+            offset += index_bits(siblings.len() + 1);
+        }
+        bytes.extend(trailer);
+
+        bytes
+    }
+
+    /// Decodes a proof encoded by `to_packed_bytes`.
+    ///
+    /// Fails if the length does not match the number of levels, or if an index is out of range.
+    pub fn from_packed_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut rest = bytes;
+        let levels = read_varint(&mut rest)? as usize;
+
+        // The sub and top tree each add a single level on top of the base tree path.
+        let compound_arities = [SubTreeArity::to_usize(), TopTreeArity::to_usize()]
+            .iter()
+            .copied()
+            .filter(|&arity| arity > 0)
+            .collect::<Vec<_>>();
+        // Every base level holds at least one sibling, bound the levels before allocating them.
+        ensure!(
+            levels >= compound_arities.len()
+                && levels - compound_arities.len()
+                    <= rest.len() / ((Arity::to_usize() - 1) * NODE_SIZE),
+            "invalid number of levels {}",
+            levels
+        );
+        let arities = std::iter::repeat(Arity::to_usize())
+            .take(levels - compound_arities.len())
+            .chain(compound_arities)
+            .collect::<Vec<_>>();
+
+        let hashes_len = arities
+            .iter()
+            .map(|arity| (arity - 1) * NODE_SIZE)
+            .sum::<usize>()
+            + 2 * NODE_SIZE;
+        let index_bits_len = arities
+            .iter()
+            .map(|&arity| index_bits(arity))
+            .sum::<usize>();
+        ensure!(
+            rest.len() == hashes_len + (index_bits_len + 7) / 8,
+            "invalid packed proof length {}",
+            bytes.len()
+        );
+        let (hashes, trailer) = rest.split_at(hashes_len);
+
+        // Interleave the hashes and the unpacked indexes into the `to_bytes` encoding.
+        let mut unpacked = Vec::with_capacity(hashes_len + levels);
+        let (mut hash_offset, mut bit_offset) = (0, 0);
+        for arity in arities {
+            let siblings_len = (arity - 1) * NODE_SIZE;
+            unpacked.extend_from_slice(&hashes[hash_offset..hash_offset + siblings_len]);
+            hash_offset += siblings_len;
+
+            let index = (0..index_bits(arity)).fold(0u8, |index, bit| {
+                let bit_offset = bit_offset + bit;
+                index | (((trailer[bit_offset / 8] >> (bit_offset % 8)) & 1) << bit)
+            });
+            unpacked.push(index);
+            bit_offset += index_bits(arity);
+        }
+        unpacked.extend_from_slice(&hashes[hash_offset..]);
+
+        Self::from_bytes(&unpacked)
+    }
+}
+
+/// Returns the number of bits needed for the indexes of a level of the given arity.
+fn index_bits(arity: usize) -> usize {
+    (usize::BITS - (arity - 1).leading_zeros()) as usize
+}
+
+/// Hashes the proof, so that proofs can be the leaves of a tree.
+///
+/// The leaf, the siblings and index of every level, and the root are folded into a single digest
+/// with `hash_md`. The leaf and that digest are then written at once as two field elements, as
+/// some hash functions, like Poseidon, only accept field elements and replace their state on every
+/// write.
+impl<
+        H: Hasher,
+        Arity: 'static + PoseidonArity,
+        SubTreeArity: 'static + PoseidonArity,
+        TopTreeArity: 'static + PoseidonArity,
+        A: std::hash::Hasher,
+    > Hashable<A> for MerkleProof<H, Arity, SubTreeArity, TopTreeArity>
+{
+    fn hash(&self, state: &mut A) {
+        let leaf = self.leaf();
+        let mut elements = vec![leaf];
+        for (siblings, index) in self.path() {
+            elements.extend(siblings);
+            elements.push(H::Domain::from(Fr::from(index as u64)));
+        }
+        elements.push(self.root());
+        let digest = H::Function::hash_md(&elements);
+
+        let mut preimage = leaf.into_bytes();
+        preimage.extend(digest.into_bytes());
+        state.write(&preimage);
+    }
+}
+
+/// Reads `levels` path elements, as written by `MerkleProof::to_bytes`, from the front of
+/// `bytes`, which must be long enough.
+fn read_path<H: Hasher, Arity: PoseidonArity>(
+    bytes: &mut &[u8],
+    levels: usize,
+) -> Result<InclusionPath<H, Arity>> {
+    let arity = Arity::to_usize();
+    let path = (0..levels)
+        .map(|_| {
+            let (hashes, rest) = bytes.split_at((arity - 1) * NODE_SIZE);
+            let hashes = hashes
+                .chunks(NODE_SIZE)
+                .map(H::Domain::try_from_bytes)
+                .collect::<Result<Vec<_>>>()?;
+            let index = rest[0] as usize;
+            ensure!(index < arity, "invalid index {} for arity {}", index, arity);
+            *bytes = &rest[1..];
+
+            Ok(PathElement {
+                hashes,
+                index,
+                _arity: PhantomData,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(path.into())
+}
+
+fn read_leaf_and_root<H: Hasher>(bytes: &[u8]) -> Result<(H::Domain, H::Domain)> {
+    let (leaf, root) = bytes.split_at(NODE_SIZE);
+    Ok((
+        H::Domain::try_from_bytes(leaf)?,
+        H::Domain::try_from_bytes(root)?,
+    ))
+}
+
+/// The `bincode` options of `bincode::serialize`, but rejecting trailing bytes.
+fn exact_bincode() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+}
+
 impl<
         H: Hasher,
         Arity: 'static + PoseidonArity,
@@ -271,6 +1068,10 @@ impl<
         }
     }
 
+    fn computed_root(&self) -> H::Domain {
+        forward_method!(self.data, computed_root)
+    }
+
     fn verify(&self) -> bool {
         forward_method!(self.data, verify)
     }
@@ -290,6 +1091,14 @@ impl<
     fn path(&self) -> Vec<(Vec<H::Domain>, usize)> {
         forward_method!(self.data, path)
     }
+
+    fn path_len(&self) -> usize {
+        forward_method!(self.data, path_len)
+    }
+
+    fn level_hashes(&self) -> Vec<H::Domain> {
+        forward_method!(self.data, level_hashes)
+    }
     fn path_index(&self) -> usize {
         forward_method!(self.data, path_index)
     }
@@ -516,9 +1325,12 @@ impl<H: Hasher, Arity: 'static + PoseidonArity> SingleProof<H, Arity> {
         Ok(proof_to_single(&p, 1, None))
     }
 
+    fn computed_root(&self) -> H::Domain {
+        self.path.root(self.leaf)
+    }
+
     fn verify(&self) -> bool {
-        let calculated_root = self.path.root(self.leaf);
-        self.root == calculated_root
+        self.root == self.computed_root()
     }
 
     fn leaf(&self) -> H::Domain {
@@ -540,9 +1352,17 @@ impl<H: Hasher, Arity: 'static + PoseidonArity> SingleProof<H, Arity> {
             .collect::<Vec<_>>()
     }
 
+    fn path_len(&self) -> usize {
+        self.path.len()
+    }
+
     fn path_index(&self) -> usize {
         self.path.path_index()
     }
+
+    fn level_hashes(&self) -> Vec<H::Domain> {
+        self.path.levels(self.leaf)
+    }
 }
 
 impl<H: Hasher, Arity: 'static + PoseidonArity, SubTreeArity: 'static + PoseidonArity>
@@ -568,11 +1388,13 @@ impl<H: Hasher, Arity: 'static + PoseidonArity, SubTreeArity: 'static + Poseidon
         Ok(SubProof::new(base_proof, sub_proof, root, leaf))
     }
 
-    fn verify(&self) -> bool {
+    fn computed_root(&self) -> H::Domain {
         let sub_leaf = self.base_proof.root(self.leaf);
-        let calculated_root = self.sub_proof.root(sub_leaf);
+        self.sub_proof.root(sub_leaf)
+    }
 
-        self.root == calculated_root
+    fn verify(&self) -> bool {
+        self.root == self.computed_root()
     }
 
     fn leaf(&self) -> H::Domain {
@@ -595,6 +1417,10 @@ impl<H: Hasher, Arity: 'static + PoseidonArity, SubTreeArity: 'static + Poseidon
             .collect()
     }
 
+    fn path_len(&self) -> usize {
+        self.base_proof.len() + self.sub_proof.len()
+    }
+
     fn path_index(&self) -> usize {
         let mut base_proof_leaves = 1;
         for _i in 0..self.base_proof.len() {
@@ -605,6 +1431,13 @@ impl<H: Hasher, Arity: 'static + PoseidonArity, SubTreeArity: 'static + Poseidon
 
         (sub_proof_index * base_proof_leaves) + self.base_proof.path_index()
     }
+
+    fn level_hashes(&self) -> Vec<H::Domain> {
+        let mut levels = self.base_proof.levels(self.leaf);
+        let sub_leaf = *levels.last().expect("levels always contain the leaf");
+        levels.extend(self.sub_proof.levels(sub_leaf).into_iter().skip(1));
+        levels
+    }
 }
 
 impl<
@@ -649,12 +1482,14 @@ impl<
         Ok(TopProof::new(base_proof, sub_proof, top_proof, root, leaf))
     }
 
-    fn verify(&self) -> bool {
+    fn computed_root(&self) -> H::Domain {
         let sub_leaf = self.base_proof.root(self.leaf);
         let top_leaf = self.sub_proof.root(sub_leaf);
-        let calculated_root = self.top_proof.root(top_leaf);
+        self.top_proof.root(top_leaf)
+    }
 
-        self.root == calculated_root
+    fn verify(&self) -> bool {
+        self.root == self.computed_root()
     }
 
     fn leaf(&self) -> H::Domain {
@@ -678,6 +1513,10 @@ impl<
             .collect()
     }
 
+    fn path_len(&self) -> usize {
+        self.base_proof.len() + self.sub_proof.len() + self.top_proof.len()
+    }
+
     fn path_index(&self) -> usize {
         let mut base_proof_leaves = 1;
         for _i in 0..self.base_proof.len() {
@@ -693,6 +1532,15 @@ impl<
             + (top_proof_index * sub_proof_leaves)
             + self.base_proof.path_index()
     }
+
+    fn level_hashes(&self) -> Vec<H::Domain> {
+        let mut levels = self.base_proof.levels(self.leaf);
+        let sub_leaf = *levels.last().expect("levels always contain the leaf");
+        levels.extend(self.sub_proof.levels(sub_leaf).into_iter().skip(1));
+        let top_leaf = *levels.last().expect("levels always contain the leaf");
+        levels.extend(self.top_proof.levels(top_leaf).into_iter().skip(1));
+        levels
+    }
 }
 
 #[cfg(test)]
@@ -706,9 +1554,22 @@ mod tests {
     use rand::thread_rng;
 
     use crate::merkle::{
-        generate_tree, get_base_tree_count, DiskStore, MerkleTreeTrait, MerkleTreeWrapper,
+        generate_tree, get_base_tree_count, BinaryMerkleTree, DiskStore, DiskTree, MerkleTreeTrait,
+        MerkleTreeWrapper,
     };
 
+    /// Applies `f` to the single proof wrapped by `proof`, panicking for compound proofs.
+    fn tamper_single<H: Hasher, Arity: PoseidonArity>(
+        proof: &mut MerkleProof<H, Arity>,
+        f: impl FnOnce(&mut SingleProof<H, Arity>),
+    ) {
+        if let ProofData::Single(ref mut single) = proof.data {
+            f(single);
+        } else {
+            panic!("expected a single proof");
+        }
+    }
+
     fn merklepath<Tree: 'static + MerkleTreeTrait>() {
         let node_size = 32;
         let nodes = 64 * get_base_tree_count::<Tree>();
@@ -901,4 +1762,916 @@ mod tests {
             >,
         >();
     }
+
+    #[test]
+    fn validate_as_subroot() {
+        let mut rng = thread_rng();
+        let (_, lower_tree) =
+            generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 16, None);
+        let lower_root = lower_tree.root();
+
+        let position = 5;
+        let mut leaves: Vec<<PoseidonHasher as Hasher>::Domain> = (0..16)
+            .map(|_| <PoseidonHasher as Hasher>::Domain::random(&mut rng))
+            .collect();
+        leaves[position] = lower_root;
+        let upper_tree =
+            BinaryMerkleTree::<PoseidonHasher>::new(leaves).expect("failed to build upper tree");
+        let upper_root = upper_tree.root();
+
+        let proof = upper_tree.gen_proof(position).expect("gen_proof failure");
+        assert!(proof.validate_as_subroot(position, &lower_root, &upper_root));
+
+        // Wrong position, wrong lower root and wrong upper root must all be rejected.
+        assert!(!proof.validate_as_subroot(position + 1, &lower_root, &upper_root));
+        assert!(!proof.validate_as_subroot(position, &upper_root, &upper_root));
+        assert!(!proof.validate_as_subroot(position, &lower_root, &lower_root));
+    }
+
+    #[test]
+    fn directions_valid() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 64, None);
+
+        let proof = tree.gen_proof(40).expect("gen_proof failure");
+        assert!(proof.directions_valid(64));
+
+        // The challenged index exceeds the claimed leaf count.
+        assert!(!proof.directions_valid(32));
+        assert!(!proof.directions_valid(0));
+    }
+
+    #[test]
+    fn hash_inner_matches_tree() {
+        let mut rng = thread_rng();
+        let leaves: Vec<<PoseidonHasher as Hasher>::Domain> = (0..8)
+            .map(|_| <PoseidonHasher as Hasher>::Domain::random(&mut rng))
+            .collect();
+        let tree =
+            BinaryMerkleTree::<PoseidonHasher>::new(leaves.clone()).expect("failed to build tree");
+
+        let mut row = leaves;
+        let mut height = 0;
+        while row.len() > 1 {
+            row = row
+                .chunks(2)
+                .map(|children| hash_inner::<PoseidonHasher>(children, height))
+                .collect();
+            height += 1;
+        }
+
+        assert_eq!(row[0], tree.root());
+    }
+
+    #[test]
+    fn matching_prefix_len() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 64, None);
+
+        let proof = tree.gen_proof(21).expect("gen_proof failure");
+        let expected_levels = proof.level_hashes();
+        assert_eq!(expected_levels.len(), proof.path().len() + 1);
+        assert_eq!(expected_levels[0], proof.leaf());
+        assert_eq!(expected_levels[expected_levels.len() - 1], proof.root());
+        assert_eq!(
+            proof.matching_prefix_len(&expected_levels),
+            proof.path().len()
+        );
+
+        // Tamper with the sibling at level 3, the first three levels still match.
+        let mut tampered = proof.clone();
+        tamper_single(&mut tampered, |single| {
+            single.path.path[3].hashes[0] = <PoseidonHasher as Hasher>::Domain::random(&mut rng);
+        });
+        assert!(!tampered.validate(21));
+        assert_eq!(tampered.matching_prefix_len(&expected_levels), 3);
+    }
+
+    #[derive(Debug)]
+    struct Record {
+        id: u64,
+        payload: Vec<u8>,
+    }
+
+    impl<A: std::hash::Hasher> Hashable<A> for Record {
+        fn hash(&self, state: &mut A) {
+            state.write(&self.id.to_le_bytes());
+            state.write(&self.payload);
+        }
+    }
+
+    #[test]
+    fn validate_record() {
+        let records: Vec<Record> = (0..8u64)
+            .map(|id| Record {
+                id,
+                payload: vec![id as u8; 40],
+            })
+            .collect();
+        let leaves = records
+            .iter()
+            .map(|record| <Sha256Hasher as Hasher>::Function::hash_leaf(record));
+        let tree = BinaryMerkleTree::<Sha256Hasher>::new(leaves).expect("failed to build tree");
+        let root = tree.root();
+
+        let proof = tree.gen_proof(3).expect("gen_proof failure");
+        assert!(proof.validate_record(3, &records[3], &root));
+
+        // A different record, position or root must be rejected.
+        assert!(!proof.validate_record(3, &records[4], &root));
+        assert!(!proof.validate_record(4, &records[3], &root));
+        assert!(!proof.validate_record(3, &records[3], &proof.leaf()));
+    }
+
+    #[test]
+    fn validate_with_data() {
+        let records: Vec<Record> = (0..8u64)
+            .map(|id| Record {
+                id,
+                payload: vec![id as u8; 40],
+            })
+            .collect();
+        let leaves = records
+            .iter()
+            .map(|record| <Sha256Hasher as Hasher>::Function::hash_leaf(record));
+        let tree = BinaryMerkleTree::<Sha256Hasher>::new(leaves).expect("failed to build tree");
+
+        let proof = tree.gen_proof(5).expect("gen_proof failure");
+        assert!(proof.validate_with_data(5, &records[5]));
+
+        // Matching data does not make up for the wrong node, and vice versa.
+        assert!(!proof.validate_with_data(4, &records[5]));
+        assert!(!proof.validate_with_data(5, &records[4]));
+    }
+
+    fn proof_hashable<H: 'static + Hasher>() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<H>, _>(&mut rng, 16, None);
+        let proof = tree.gen_proof(6).expect("gen_proof failure");
+
+        let digest = H::Function::hash_leaf(&proof);
+        assert_eq!(H::Function::hash_leaf(&proof.clone()), digest);
+        assert_ne!(
+            H::Function::hash_leaf(&tree.gen_proof(7).expect("gen_proof failure")),
+            digest
+        );
+
+        // Flipping the direction of a single level changes the digest.
+        let mut flipped = proof.clone();
+        tamper_single(&mut flipped, |single| {
+            single.path.path[2].index ^= 1;
+        });
+        assert_ne!(H::Function::hash_leaf(&flipped), digest);
+
+        // Proofs can be the leaves of a tree.
+        let proofs_tree = BinaryMerkleTree::<H>::new(
+            (0..16).map(|i| H::Function::hash_leaf(&tree.gen_proof(i).expect("gen_proof failure"))),
+        )
+        .expect("failed to build tree");
+        assert!(proofs_tree
+            .gen_proof(6)
+            .expect("gen_proof failure")
+            .validate_data(digest));
+    }
+
+    #[test]
+    fn proof_hashable_poseidon() {
+        proof_hashable::<PoseidonHasher>();
+    }
+
+    #[test]
+    fn proof_hashable_sha256() {
+        proof_hashable::<Sha256Hasher>();
+    }
+
+    #[test]
+    fn same_tree() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 16, None);
+        let (_, other_tree) =
+            generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 16, None);
+
+        let a = tree.gen_proof(2).expect("gen_proof failure");
+        let b = tree.gen_proof(11).expect("gen_proof failure");
+        let c = other_tree.gen_proof(2).expect("gen_proof failure");
+
+        assert!(a.same_tree(&b));
+        assert!(b.same_tree(&a));
+        assert!(!a.same_tree(&c));
+    }
+
+    #[test]
+    fn validate_and_describe() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 16, None);
+        let root = tree.root();
+
+        let proof = tree.gen_proof(9).expect("gen_proof failure");
+        assert_eq!(
+            proof.validate_and_describe(&root),
+            Some(ProofInfo { index: 9, depth: 4 })
+        );
+        assert_eq!(proof.validate_and_describe(&proof.leaf()), None);
+
+        let mut tampered = proof;
+        tamper_single(&mut tampered, |single| {
+            single.path.path[1].hashes[0] = <PoseidonHasher as Hasher>::Domain::random(&mut rng);
+        });
+        assert_eq!(tampered.validate_and_describe(&root), None);
+
+        // The leaf of an empty proof equals its root, it is still invalid.
+        let empty = MerkleProof::<PoseidonHasher, U2>::new(0);
+        assert!(!empty.validate(0));
+        assert_eq!(empty.validate_and_describe(&empty.root()), None);
+    }
+
+    #[test]
+    fn validate_with_path_len() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 32, None);
+
+        let proof = tree.gen_proof(12).expect("gen_proof failure");
+        assert_eq!(proof.validate_with_path_len(12), (true, proof.path().len()));
+        assert_eq!(
+            proof.validate_with_path_len(13),
+            (false, proof.path().len())
+        );
+
+        let mut tampered = proof.clone();
+        tamper_single(&mut tampered, |single| {
+            single.path.path[0].hashes[0] = <PoseidonHasher as Hasher>::Domain::random(&mut rng);
+        });
+        assert_eq!(
+            tampered.validate_with_path_len(12),
+            (false, proof.path().len())
+        );
+
+        // The leaf of an empty proof equals its root, it is still invalid.
+        let empty = MerkleProof::<PoseidonHasher, U2>::new(0);
+        assert_eq!(empty.leaf(), empty.root());
+        assert_eq!(empty.validate_with_path_len(0), (false, 0));
+    }
+
+    #[test]
+    fn validate_tagged() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<Sha256Hasher>, _>(&mut rng, 16, None);
+        let root = tree.root();
+
+        let tagged = TaggedProof::new(tree.gen_proof(3).expect("gen_proof failure"));
+        assert_eq!(tagged.kind(), HashKind::Sha256);
+        assert!(tagged.validate_tagged(3, &root));
+        assert!(!tagged.validate_tagged(4, &root));
+
+        // The tag survives a round trip, and a proof tagged with another hash is rejected.
+        let json = serde_json::to_string(&tagged).expect("failed to serialize");
+        let decoded: TaggedProof<MerkleProof<Sha256Hasher, U2>> =
+            serde_json::from_str(&json).expect("failed to deserialize");
+        assert_eq!(decoded.kind(), HashKind::Sha256);
+        assert!(decoded.validate_tagged(3, &root));
+
+        for kind in &[HashKind::Poseidon, HashKind::Blake2s] {
+            let mut value = serde_json::to_value(&tagged).expect("failed to serialize");
+            value["kind"] = serde_json::to_value(kind).expect("failed to serialize");
+            let retagged: TaggedProof<MerkleProof<Sha256Hasher, U2>> =
+                serde_json::from_value(value).expect("failed to deserialize");
+            assert_eq!(retagged.kind(), *kind);
+            assert!(!retagged.validate_tagged(3, &root));
+        }
+    }
+
+    #[test]
+    fn parse_exact() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 16, None);
+
+        let proof = tree.gen_proof(5).expect("gen_proof failure");
+        let mut bytes = bincode::serialize(&proof).expect("serialize failure");
+
+        let parsed =
+            MerkleProof::<PoseidonHasher, U2>::parse_exact(&bytes, 4).expect("parse_exact failure");
+        assert_eq!(parsed.path(), proof.path());
+        assert!(parsed.validate(5));
+
+        assert!(MerkleProof::<PoseidonHasher, U2>::parse_exact(&bytes, 3).is_err());
+        assert!(
+            MerkleProof::<PoseidonHasher, U2>::parse_exact(&bytes[..bytes.len() - 1], 4).is_err()
+        );
+
+        // A single trailing byte must be rejected.
+        bytes.push(0);
+        assert!(MerkleProof::<PoseidonHasher, U2>::parse_exact(&bytes, 4).is_err());
+    }
+
+    fn bytes_round_trip<
+        U: 'static + PoseidonArity,
+        V: 'static + PoseidonArity,
+        W: 'static + PoseidonArity,
+    >() {
+        let nodes = 64 * get_base_tree_count::<DiskTree<PoseidonHasher, U, V, W>>();
+        let mut rng = thread_rng();
+        let (_, tree) =
+            generate_tree::<DiskTree<PoseidonHasher, U, V, W>, _>(&mut rng, nodes, None);
+
+        for &i in &[0, 5, nodes - 1] {
+            let proof = tree.gen_proof(i).expect("gen_proof failure");
+            let mut bytes = proof.to_bytes();
+            assert_eq!(
+                bytes.len(),
+                proof
+                    .path()
+                    .iter()
+                    .map(|(siblings, _)| siblings.len() * NODE_SIZE + 1)
+                    .sum::<usize>()
+                    + 2 * NODE_SIZE
+            );
+
+            let decoded = MerkleProof::<PoseidonHasher, U, V, W>::from_bytes(&bytes)
+                .expect("from_bytes failure");
+            assert_eq!(decoded.path(), proof.path());
+            assert_eq!(decoded.leaf(), proof.leaf());
+            assert_eq!(decoded.root(), proof.root());
+            assert!(decoded.validate(i));
+
+            assert!(MerkleProof::<PoseidonHasher, U, V, W>::from_bytes(&bytes[1..]).is_err());
+
+            // The first index byte follows the siblings of the first level.
+            let index_offset = (U::to_usize() - 1) * NODE_SIZE;
+            bytes[index_offset] = U::to_usize() as u8;
+            assert!(MerkleProof::<PoseidonHasher, U, V, W>::from_bytes(&bytes).is_err());
+        }
+    }
+
+    #[test]
+    fn compact_bytes_round_trip() {
+        let mut rng = thread_rng();
+        let (_, tree) =
+            generate_tree::<DiskTree<PoseidonHasher, U8, U4, U0>, _>(&mut rng, 256, None);
+        let (_, other_tree) =
+            generate_tree::<DiskTree<PoseidonHasher, U8, U4, U0>, _>(&mut rng, 256, None);
+        let root = tree.root();
+
+        let proof = tree.gen_proof(77).expect("gen_proof failure");
+        let bytes = proof.to_compact_bytes();
+        assert_eq!(bytes.len(), proof.to_bytes().len() - NODE_SIZE);
+
+        let decoded = MerkleProof::<PoseidonHasher, U8, U4>::from_compact_bytes(&bytes, &root)
+            .expect("from_compact_bytes failure");
+        assert_eq!(decoded.path(), proof.path());
+        assert_eq!(decoded.leaf(), proof.leaf());
+        assert_eq!(decoded.root(), root);
+        assert!(decoded.validate(77));
+
+        // The path does not lead to the root of another tree.
+        let foreign =
+            MerkleProof::<PoseidonHasher, U8, U4>::from_compact_bytes(&bytes, &other_tree.root())
+                .expect("from_compact_bytes failure");
+        assert!(!foreign.validate(77));
+
+        assert!(
+            MerkleProof::<PoseidonHasher, U8, U4>::from_compact_bytes(&bytes[1..], &root).is_err()
+        );
+    }
+
+    fn packed_bytes_round_trip<
+        U: 'static + PoseidonArity,
+        V: 'static + PoseidonArity,
+        W: 'static + PoseidonArity,
+    >() {
+        let nodes = 64 * get_base_tree_count::<DiskTree<PoseidonHasher, U, V, W>>();
+        let mut rng = thread_rng();
+        let (_, tree) =
+            generate_tree::<DiskTree<PoseidonHasher, U, V, W>, _>(&mut rng, nodes, None);
+
+        for &i in &[0, 9, nodes - 1] {
+            let proof = tree.gen_proof(i).expect("gen_proof failure");
+            let bytes = proof.to_packed_bytes();
+            assert!(bytes.len() < proof.to_bytes().len());
+            assert!(bytes.len() < bincode::serialize(&proof).expect("serialize failure").len());
+
+            let decoded = MerkleProof::<PoseidonHasher, U, V, W>::from_packed_bytes(&bytes)
+                .expect("from_packed_bytes failure");
+            assert_eq!(decoded.path(), proof.path());
+            assert_eq!(decoded.leaf(), proof.leaf());
+            assert_eq!(decoded.root(), proof.root());
+            assert!(decoded.validate(i));
+
+            assert!(MerkleProof::<PoseidonHasher, U, V, W>::from_packed_bytes(
+                &bytes[..bytes.len() - 1]
+            )
+            .is_err());
+        }
+
+        // Level counts not backed by enough bytes are rejected before allocating the levels.
+        for &levels in &[0, 1, u32::MAX] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, levels);
+            bytes.extend_from_slice(&[0; 3 * NODE_SIZE]);
+            assert!(MerkleProof::<PoseidonHasher, U, V, W>::from_packed_bytes(&bytes).is_err());
+        }
+    }
+
+    #[test]
+    fn packed_bytes_round_trip_binary() {
+        packed_bytes_round_trip::<U2, U0, U0>();
+
+        // A binary path of 6 levels packs its indexes into a single byte.
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 64, None);
+        let proof = tree.gen_proof(0b101101).expect("gen_proof failure");
+        let bytes = proof.to_packed_bytes();
+        assert_eq!(bytes.len(), 1 + (6 + 2) * NODE_SIZE + 1);
+        assert_eq!(bytes[bytes.len() - 1], 0b101101);
+    }
+
+    #[test]
+    fn packed_bytes_round_trip_oct_4_2() {
+        packed_bytes_round_trip::<U8, U4, U2>();
+    }
+
+    #[test]
+    fn write_to() {
+        let nodes = 64 * get_base_tree_count::<DiskTree<PoseidonHasher, U8, U2, U0>>();
+        let mut rng = thread_rng();
+        let (_, tree) =
+            generate_tree::<DiskTree<PoseidonHasher, U8, U2, U0>, _>(&mut rng, nodes, None);
+
+        // Proofs streamed into one sink are the concatenation of their encodings.
+        let mut streamed = Vec::new();
+        let mut expected = Vec::new();
+        for i in 0..nodes {
+            let proof = tree.gen_proof(i).expect("gen_proof failure");
+            let mut bytes = Vec::new();
+            proof.write_to(&mut bytes).expect("write_to failure");
+            assert_eq!(bytes, proof.to_bytes());
+
+            proof.write_to(&mut streamed).expect("write_to failure");
+            expected.extend(proof.to_bytes());
+        }
+        assert_eq!(streamed, expected);
+    }
+
+    #[test]
+    fn bytes_round_trip_binary() {
+        bytes_round_trip::<U2, U0, U0>();
+    }
+
+    #[test]
+    fn bytes_round_trip_oct_4_2() {
+        bytes_round_trip::<U8, U4, U2>();
+    }
+
+    #[test]
+    fn top_level_proofs() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 16, None);
+        let root = tree.root();
+
+        let proofs = super::top_level_proofs(&tree).expect("top_level_proofs failure");
+        assert_eq!(proofs.len(), 2);
+        for (i, proof) in proofs.iter().enumerate() {
+            assert!(proof.validate(i));
+            assert_eq!(proof.root(), root);
+            assert_eq!(proof.path().len(), 1);
+        }
+        assert_eq!(
+            hash_inner::<PoseidonHasher>(&[proofs[0].leaf(), proofs[1].leaf()], 3),
+            root
+        );
+    }
+
+    #[test]
+    fn validate_non_zero() {
+        let mut rng = thread_rng();
+        let mut leaves: Vec<<PoseidonHasher as Hasher>::Domain> = (0..16)
+            .map(|_| <PoseidonHasher as Hasher>::Domain::random(&mut rng))
+            .collect();
+        // Position 3 holds corrupt data, the last 4 positions are padding.
+        leaves[3] = Default::default();
+        for leaf in leaves.iter_mut().skip(12) {
+            *leaf = Default::default();
+        }
+        let tree = BinaryMerkleTree::<PoseidonHasher>::new(leaves).expect("failed to build tree");
+
+        let zeroed = tree.gen_proof(3).expect("gen_proof failure");
+        assert!(zeroed.leaf_is_zero());
+        assert!(zeroed.validate(3));
+        assert!(!zeroed.validate_non_zero(3, 12));
+
+        let padding = tree.gen_proof(13).expect("gen_proof failure");
+        assert!(padding.leaf_is_zero());
+        assert!(padding.validate_non_zero(13, 12));
+
+        let data = tree.gen_proof(4).expect("gen_proof failure");
+        assert!(!data.leaf_is_zero());
+        assert!(data.validate_non_zero(4, 12));
+    }
+
+    #[test]
+    fn framed() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 16, None);
+        let proofs = [0, 5, 15]
+            .iter()
+            .map(|&i| tree.gen_proof(i).expect("gen_proof failure"))
+            .collect::<Vec<_>>();
+
+        let mut stream = Vec::new();
+        for proof in &proofs {
+            proof
+                .write_framed(&mut stream)
+                .expect("write_framed failure");
+        }
+
+        let mut reader = stream.as_slice();
+        for proof in &proofs {
+            let read = MerkleProof::<PoseidonHasher, U2>::read_framed(&mut reader)
+                .expect("read_framed failure");
+            assert_eq!(read.path(), proof.path());
+            assert_eq!(read.leaf(), proof.leaf());
+            assert_eq!(read.root(), proof.root());
+        }
+
+        // The stream is exhausted.
+        assert!(reader.is_empty());
+        assert!(MerkleProof::<PoseidonHasher, U2>::read_framed(&mut reader).is_err());
+
+        // A length prefix exceeding the frame is rejected.
+        let mut oversized = stream.clone();
+        oversized[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(MerkleProof::<PoseidonHasher, U2>::read_framed(&mut oversized.as_slice()).is_err());
+    }
+
+    #[test]
+    fn validate_committed() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 16, None);
+        let (_, other_tree) =
+            generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 16, None);
+        let root = tree.root();
+        let real_leaf_count = 10;
+
+        let proof = tree.gen_proof(7).expect("gen_proof failure");
+        assert!(proof.validate_committed(7, &root, real_leaf_count));
+        assert!(!proof.validate_committed(7, &other_tree.root(), real_leaf_count));
+        assert!(!proof.validate_committed(6, &root, real_leaf_count));
+
+        // Valid proofs of padding positions are rejected.
+        let padding = tree.gen_proof(12).expect("gen_proof failure");
+        assert!(padding.validate(12));
+        assert!(!padding.validate_committed(12, &root, real_leaf_count));
+    }
+
+    #[test]
+    fn validate_from_frs() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 32, None);
+
+        let proof = tree.gen_proof(19).expect("gen_proof failure");
+        let leaf: Fr = proof.leaf().into();
+        let root: Fr = proof.root().into();
+        let mut path = proof.as_pairs();
+        assert!(super::validate_from_frs::<PoseidonHasher>(
+            leaf, &path, root
+        ));
+        assert!(!super::validate_from_frs::<PoseidonHasher>(
+            root, &path, root
+        ));
+        assert!(!super::validate_from_frs::<PoseidonHasher>(
+            leaf, &path, leaf
+        ));
+
+        path[2].1 ^= 1;
+        assert!(!super::validate_from_frs::<PoseidonHasher>(
+            leaf, &path, root
+        ));
+        path[2].1 = 2;
+        assert!(!super::validate_from_frs::<PoseidonHasher>(
+            leaf, &path, root
+        ));
+    }
+
+    #[test]
+    fn is_minimal() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 32, None);
+
+        let proof = tree.gen_proof(9).expect("gen_proof failure");
+        assert!(proof.is_minimal(32));
+        assert!(!proof.is_minimal(64));
+
+        // Pad the proof with a default filler level.
+        let mut padded = proof;
+        tamper_single(&mut padded, |single| {
+            single.path.path.push(PathElement {
+                hashes: vec![Default::default()],
+                index: 0,
+                _arity: PhantomData,
+            });
+        });
+        assert!(!padded.is_minimal(32));
+    }
+
+    #[test]
+    fn validate_for_tree() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 32, None);
+
+        let proof = tree.gen_proof(9).expect("gen_proof failure");
+        assert!(proof.validate_for_tree(9, 32));
+        assert!(!proof.validate_for_tree(10, 32));
+        assert!(!proof.validate_for_tree(9, 64));
+
+        // Inject an extra level and claim the root it folds to.
+        let mut padded = proof;
+        tamper_single(&mut padded, |single| {
+            single.path.path.push(PathElement {
+                hashes: vec![<PoseidonHasher as Hasher>::Domain::random(&mut rng)],
+                index: 0,
+                _arity: PhantomData,
+            });
+            single.root = single.computed_root();
+        });
+        assert!(padded.validate(9));
+        assert!(!padded.validate_for_tree(9, 32));
+    }
+
+    #[test]
+    fn validate_under_commitment() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 16, None);
+        let proof = tree.gen_proof(6).expect("gen_proof failure");
+
+        let other: Fr = <PoseidonHasher as Hasher>::Domain::random(&mut rng).into();
+        let fields = [other, tree.root().into(), other];
+        let commitment = <PoseidonHasher as Hasher>::Function::hash_md(
+            &fields.iter().copied().map(Into::into).collect::<Vec<_>>(),
+        );
+
+        assert!(proof.validate_under_commitment(6, &fields, 1, &commitment));
+        assert!(!proof.validate_under_commitment(7, &fields, 1, &commitment));
+
+        // The root is not committed at another position.
+        assert!(!proof.validate_under_commitment(6, &fields, 0, &commitment));
+        assert!(!proof.validate_under_commitment(6, &fields, 3, &commitment));
+
+        // Nor under a commitment with the root moved elsewhere.
+        let moved = [tree.root().into(), other, other];
+        assert!(!proof.validate_under_commitment(6, &moved, 1, &commitment));
+
+        assert!(!proof.validate_under_commitment(6, &fields[1..2], 0, &tree.root()));
+    }
+
+    #[test]
+    fn validate_with_levels() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 16, None);
+        let mut proof = tree.gen_proof(9).expect("gen_proof failure");
+
+        let levels = proof.validate_with_levels(9).expect("proof is valid");
+        assert_eq!(levels.len(), proof.path().len() + 1);
+        assert_eq!(levels.first(), Some(&proof.leaf()));
+        assert_eq!(levels.last(), Some(&proof.root()));
+        assert!(proof.validate_with_levels(8).is_none());
+
+        tamper_single(&mut proof, |single| {
+            single.path.path[1].hashes[0] = <PoseidonHasher as Hasher>::Domain::random(&mut rng);
+        });
+        assert!(proof.validate_with_levels(9).is_none());
+
+        let empty = MerkleProof::<PoseidonHasher, U2>::new(0);
+        assert!(empty.validate_with_levels(0).is_none());
+    }
+
+    #[test]
+    fn validate_ct() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 32, None);
+
+        for i in 0..32 {
+            let proof = tree.gen_proof(i).expect("gen_proof failure");
+            assert!(proof.validate_ct(i));
+            assert_eq!(
+                proof.validate_ct((i + 1) % 32),
+                proof.validate((i + 1) % 32)
+            );
+
+            let mut tampered = proof.clone();
+            tamper_single(&mut tampered, |single| {
+                single.path.path[i % 5].hashes[0] =
+                    <PoseidonHasher as Hasher>::Domain::random(&mut rng);
+            });
+            assert!(!tampered.validate(i));
+            assert!(!tampered.validate_ct(i));
+        }
+
+        let empty = MerkleProof::<PoseidonHasher, U2>::new(0);
+        assert!(!empty.validate_ct(0));
+    }
+
+    #[test]
+    fn check() {
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, 16, None);
+        let mut proof = tree.gen_proof(11).expect("gen_proof failure");
+
+        assert_eq!(proof.check(11), Ok(()));
+        assert_eq!(
+            proof.check(10),
+            Err(ProofError::PathIndexMismatch {
+                expected: 10,
+                got: 11
+            })
+        );
+        assert!(!proof.validate(10));
+
+        tamper_single(&mut proof, |single| {
+            single.leaf = <PoseidonHasher as Hasher>::Domain::random(&mut rng);
+        });
+        assert_eq!(proof.check(11), Err(ProofError::RootMismatch));
+        assert!(!proof.validate(11));
+
+        let empty = MerkleProof::<PoseidonHasher, U2>::new(0);
+        assert_eq!(empty.check(0), Err(ProofError::EmptyPath));
+        assert!(!empty.validate(0));
+    }
+
+    fn serde_round_trip<Tree: 'static + MerkleTreeTrait>() {
+        let nodes = 64 * get_base_tree_count::<Tree>();
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<Tree, _>(&mut rng, nodes, None);
+
+        for &i in &[0, 7, nodes - 1] {
+            let proof = tree.gen_proof(i).expect("gen_proof failure");
+            assert_eq!(proof.path_len(), proof.path().len());
+
+            let json = serde_json::to_string(&proof).expect("json serialize failure");
+            let from_json: Tree::Proof =
+                serde_json::from_str(&json).expect("json deserialize failure");
+            assert_eq!(from_json.path(), proof.path());
+            assert_eq!(from_json.leaf(), proof.leaf());
+            assert_eq!(from_json.root(), proof.root());
+            assert!(from_json.validate(i));
+
+            let bytes = bincode::serialize(&proof).expect("bincode serialize failure");
+            let from_bincode: Tree::Proof =
+                bincode::deserialize(&bytes).expect("bincode deserialize failure");
+            assert_eq!(from_bincode.path(), proof.path());
+            assert_eq!(from_bincode.leaf(), proof.leaf());
+            assert_eq!(from_bincode.root(), proof.root());
+            assert!(from_bincode.validate(i));
+            assert_eq!(
+                bincode::serialize(&from_json).expect("bincode serialize failure"),
+                bytes
+            );
+        }
+    }
+
+    #[test]
+    fn serde_round_trip_poseidon_2() {
+        serde_round_trip::<BinaryMerkleTree<PoseidonHasher>>();
+    }
+
+    #[test]
+    fn serde_round_trip_poseidon_8_4_2() {
+        serde_round_trip::<DiskTree<PoseidonHasher, U8, U4, U2>>();
+    }
+
+    #[test]
+    fn serde_round_trip_sha256_4_2() {
+        serde_round_trip::<DiskTree<Sha256Hasher, U4, U2, U0>>();
+    }
+
+    fn challenged_node<Tree: 'static + MerkleTreeTrait>() {
+        let nodes = 64 * get_base_tree_count::<Tree>();
+        let mut rng = thread_rng();
+        let (_, tree) = generate_tree::<Tree, _>(&mut rng, nodes, None);
+
+        for i in 0..nodes {
+            let proof = tree.gen_proof(i).expect("gen_proof failure");
+            assert_eq!(proof.challenged_node(), i);
+        }
+    }
+
+    #[test]
+    fn challenged_node_binary() {
+        challenged_node::<DiskTree<PoseidonHasher, U2, U0, U0>>();
+    }
+
+    #[test]
+    fn challenged_node_oct_4_2() {
+        challenged_node::<DiskTree<PoseidonHasher, U8, U4, U2>>();
+    }
+
+    #[test]
+    fn as_circuit_inputs() {
+        let nodes = 64 * get_base_tree_count::<DiskTree<PoseidonHasher, U8, U4, U2>>();
+        let mut rng = thread_rng();
+        let (_, tree) =
+            generate_tree::<DiskTree<PoseidonHasher, U8, U4, U2>, _>(&mut rng, nodes, None);
+
+        let proof = tree.gen_proof(300).expect("gen_proof failure");
+        let (hashes, indexes) = proof.as_circuit_inputs();
+        let pairs = proof.as_pairs();
+        assert_eq!(indexes.len(), pairs.len());
+
+        let mut hashes = hashes.into_iter();
+        for ((siblings, index), expected_index) in pairs.into_iter().zip(indexes) {
+            assert_eq!(index, expected_index);
+            assert_eq!(
+                siblings,
+                hashes.by_ref().take(siblings.len()).collect::<Vec<_>>()
+            );
+        }
+        assert!(hashes.next().is_none());
+    }
+
+    fn computed_root<
+        U: 'static + PoseidonArity,
+        V: 'static + PoseidonArity,
+        W: 'static + PoseidonArity,
+    >() {
+        let nodes = 64 * get_base_tree_count::<DiskTree<PoseidonHasher, U, V, W>>();
+        let mut rng = thread_rng();
+        let (_, tree) =
+            generate_tree::<DiskTree<PoseidonHasher, U, V, W>, _>(&mut rng, nodes, None);
+
+        for &i in &[0, 21, nodes - 1] {
+            let proof = tree.gen_proof(i).expect("gen_proof failure");
+            assert_eq!(proof.computed_root(), tree.root());
+            assert_eq!(
+                proof.computed_root(),
+                *proof.level_hashes().last().expect("no levels")
+            );
+
+            // A corrupted root is detected, while the path and leaf still lead to the real one.
+            let mut bytes = proof.to_bytes();
+            let root_offset = bytes.len() - NODE_SIZE;
+            bytes[root_offset..].copy_from_slice(&proof.leaf().into_bytes());
+            let corrupted = MerkleProof::<PoseidonHasher, U, V, W>::from_bytes(&bytes)
+                .expect("from_bytes failure");
+            assert_ne!(corrupted.root(), tree.root());
+            assert!(!corrupted.verify());
+            assert!(!corrupted.validate(i));
+            assert_eq!(corrupted.computed_root(), tree.root());
+        }
+    }
+
+    #[test]
+    fn computed_root_binary() {
+        computed_root::<U2, U0, U0>();
+    }
+
+    #[test]
+    fn computed_root_oct_4_2() {
+        computed_root::<U8, U4, U2>();
+    }
+
+    fn expected_size<
+        U: 'static + PoseidonArity,
+        V: 'static + PoseidonArity,
+        W: 'static + PoseidonArity,
+    >() {
+        let nodes = 64 * get_base_tree_count::<DiskTree<PoseidonHasher, U, V, W>>();
+        let mut rng = thread_rng();
+        let (_, tree) =
+            generate_tree::<DiskTree<PoseidonHasher, U, V, W>, _>(&mut rng, nodes, None);
+
+        let expected = expected_proof_size::<U, V, W>(nodes, NODE_SIZE);
+        for &i in &[0, 9, nodes - 1] {
+            let proof = tree.gen_proof(i).expect("gen_proof failure");
+            assert_eq!(proof.to_bytes().len(), expected);
+        }
+    }
+
+    #[test]
+    fn expected_size_binary() {
+        expected_size::<U2, U0, U0>();
+    }
+
+    #[test]
+    fn expected_size_oct_4_2() {
+        expected_size::<U8, U4, U2>();
+    }
+
+    #[test]
+    fn arbitrary_proof() {
+        let mut rng = thread_rng();
+
+        for depth in 1..6 {
+            let (proof, node) = super::arbitrary_proof::<Sha256Hasher, U2, _>(&mut rng, depth);
+            assert_eq!(proof.path().len(), depth);
+            assert!(proof.validate(node));
+
+            // Flipping any bit of the encoding breaks validation.
+            let bytes = proof.to_bytes();
+            for i in 0..bytes.len() {
+                let mut flipped = bytes.clone();
+                flipped[i] ^= 1;
+                if let Ok(flipped) = MerkleProof::<Sha256Hasher, U2>::from_bytes(&flipped) {
+                    assert!(!flipped.validate(node));
+                }
+            }
+        }
+    }
 }
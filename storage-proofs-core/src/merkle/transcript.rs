@@ -0,0 +1,235 @@
+use std::convert::TryInto;
+
+use anyhow::{ensure, Result};
+use blstrs::Scalar as Fr;
+use filecoin_hashers::{HashFunction, Hasher};
+use fr32::bytes_into_fr_repr_safe;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::merkle::{create_base_merkle_tree_with_node_size, MerkleProofTrait, MerkleTreeTrait};
+
+/// A Fiat-Shamir transcript, accumulating verified commitments and deriving challenges from them.
+///
+/// The state is folded with `HashFunction::hash2` of the hasher `H`, so identical sequences of
+/// absorbed values always produce identical challenges.
+#[derive(Debug, Clone)]
+pub struct Transcript<H: Hasher> {
+    state: H::Domain,
+    leaves: usize,
+}
+
+impl<H: Hasher> Transcript<H> {
+    /// Creates an empty transcript, deriving challenges in `0..leaves`.
+    pub fn new(leaves: usize) -> Result<Self> {
+        ensure!(leaves > 0, "leaves must be greater than zero");
+        Ok(Transcript {
+            state: H::Domain::default(),
+            leaves,
+        })
+    }
+
+    /// Absorbs a tree root.
+    pub fn absorb_root(&mut self, root: &H::Domain) {
+        self.state = H::Function::hash2(&self.state, root);
+    }
+
+    /// Absorbs arbitrary bytes, e.g. a seed.
+    ///
+    /// The bytes are hashed with `Sha256` into a field element first, as not every hasher accepts
+    /// arbitrary input.
+    pub fn absorb_bytes(&mut self, bytes: &[u8]) {
+        let digest = Sha256::digest(bytes);
+        let element = H::Domain::from(bytes_into_fr_repr_safe(digest.as_ref()));
+        self.state = H::Function::hash2(&self.state, &element);
+    }
+
+    /// Absorbs the leaf, all path elements and the root of `proof`.
+    pub fn absorb_proof<P: MerkleProofTrait<Hasher = H>>(&mut self, proof: &P) {
+        self.state = H::Function::hash2(&self.state, &proof.leaf());
+        for (hashes, index) in proof.path() {
+            for hash in &hashes {
+                self.state = H::Function::hash2(&self.state, hash);
+            }
+            self.state = H::Function::hash2(&self.state, &H::Domain::from(Fr::from(index as u64)));
+        }
+        self.absorb_root(&proof.root());
+    }
+
+    /// Derives `n` challenges from the current state.
+    ///
+    /// The state is advanced afterwards, so consecutive calls return different challenges.
+    pub fn challenge(&mut self, n: usize) -> Vec<usize> {
+        let challenges = (0..n)
+            .map(|i| {
+                let digest = H::Function::hash2(&self.state, &H::Domain::from(Fr::from(i as u64)));
+                let bytes: [u8; 8] = digest.as_ref()[..8]
+                    .try_into()
+                    .expect("domain is at least 8 bytes");
+                (u64::from_le_bytes(bytes) % self.leaves as u64) as usize
+            })
+            .collect();
+        self.state = H::Function::hash2(&self.state, &H::Domain::from(Fr::from(n as u64)));
+
+        challenges
+    }
+}
+
+/// A tree root together with the proofs of the challenges derived from it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChallengeResponse<P: MerkleProofTrait> {
+    #[serde(bound = "")]
+    pub root: <P::Hasher as Hasher>::Domain,
+    #[serde(bound = "")]
+    pub proofs: Vec<P>,
+}
+
+impl<P: MerkleProofTrait> ChallengeResponse<P> {
+    /// Re-derives the `k` challenges from the root and `seed`, and validates the proof of each.
+    pub fn validate(&self, seed: &[u8], k: usize, leaf_count: usize) -> bool {
+        if leaf_count == 0 || self.proofs.len() != k {
+            return false;
+        }
+
+        match derive_challenges::<P::Hasher>(&self.root, seed, k, leaf_count) {
+            Ok(challenges) => challenges
+                .into_iter()
+                .zip(self.proofs.iter())
+                .all(|(challenge, proof)| proof.root() == self.root && proof.validate(challenge)),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Builds a base tree over the `node_size` byte nodes of `data`, see
+/// `create_base_merkle_tree_with_node_size`, derives `k` challenges from its root and `seed` with
+/// a `Transcript`, and responds with the root and the proofs of all challenged leaves.
+pub fn respond_to_challenge<Tree: MerkleTreeTrait>(
+    data: &[u8],
+    node_size: usize,
+    seed: &[u8],
+    k: usize,
+) -> Result<ChallengeResponse<Tree::Proof>> {
+    let tree = create_base_merkle_tree_with_node_size::<Tree>(None, data, node_size)?;
+    let leaf_count = data.len() / node_size;
+    let root = tree.root();
+    let proofs = derive_challenges::<Tree::Hasher>(&root, seed, k, leaf_count)?
+        .into_iter()
+        .map(|challenge| tree.gen_proof(challenge))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(ChallengeResponse { root, proofs })
+}
+
+fn derive_challenges<H: Hasher>(
+    root: &H::Domain,
+    seed: &[u8],
+    k: usize,
+    leaf_count: usize,
+) -> Result<Vec<usize>> {
+    let mut transcript = Transcript::<H>::new(leaf_count)?;
+    transcript.absorb_bytes(seed);
+    transcript.absorb_root(root);
+    Ok(transcript.challenge(k))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use filecoin_hashers::{poseidon::PoseidonHasher, Domain};
+    use rand::thread_rng;
+
+    use crate::{
+        merkle::{generate_tree, BinaryMerkleTree, MerkleTreeTrait},
+        util::NODE_SIZE,
+    };
+
+    #[test]
+    fn test_transcript() {
+        let leaves = 64;
+        let mut rng = thread_rng();
+        let (_, tree_a) =
+            generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, leaves, None);
+        let (_, tree_b) =
+            generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, leaves, None);
+        let proof = tree_a.gen_proof(17).expect("gen_proof failure");
+
+        let run = |root: &<PoseidonHasher as Hasher>::Domain| {
+            let mut transcript =
+                Transcript::<PoseidonHasher>::new(leaves).expect("transcript new failure");
+            transcript.absorb_root(root);
+            transcript.absorb_proof(&proof);
+            let first = transcript.challenge(10);
+            let second = transcript.challenge(10);
+            (first, second)
+        };
+
+        let (first, second) = run(&tree_a.root());
+        assert_eq!(first.len(), 10);
+        assert!(first.iter().chain(second.iter()).all(|&c| c < leaves));
+        assert_ne!(first, second);
+
+        // Identical absorb sequences yield identical challenges, different ones do not.
+        assert_eq!(run(&tree_a.root()), (first.clone(), second));
+        assert_ne!(run(&tree_b.root()).0, first);
+
+        assert!(Transcript::<PoseidonHasher>::new(0).is_err());
+    }
+
+    #[test]
+    fn test_transcript_absorb_bytes() {
+        let run = |bytes: &[u8]| {
+            let mut transcript =
+                Transcript::<PoseidonHasher>::new(64).expect("transcript new failure");
+            transcript.absorb_bytes(bytes);
+            transcript.challenge(10)
+        };
+
+        // Bytes of any length are accepted.
+        for len in &[0, 1, 14, 32, 100] {
+            let bytes = vec![0xff; *len];
+            assert_eq!(run(&bytes), run(&bytes));
+        }
+        assert_ne!(run(b"a seed"), run(b"another seed"));
+    }
+
+    #[test]
+    fn test_respond_to_challenge() {
+        let leaves = 64;
+        let k = 5;
+        let seed = b"challenge seed";
+        let mut rng = thread_rng();
+        let (data, tree) =
+            generate_tree::<BinaryMerkleTree<PoseidonHasher>, _>(&mut rng, leaves, None);
+
+        let response =
+            respond_to_challenge::<BinaryMerkleTree<PoseidonHasher>>(&data, NODE_SIZE, seed, k)
+                .expect("respond_to_challenge failure");
+        assert_eq!(response.root, tree.root());
+        assert_eq!(response.proofs.len(), k);
+        assert!(response.validate(seed, k, leaves));
+
+        // The challenges are bound to the seed and the number of challenges.
+        assert!(!response.validate(b"other seed", k, leaves));
+        assert!(!response.validate(seed, k - 1, leaves));
+
+        let mut tampered = response;
+        tampered.root = <PoseidonHasher as Hasher>::Domain::random(&mut rng);
+        assert!(!tampered.validate(seed, k, leaves));
+
+        // Smaller nodes yield more leaves.
+        let response =
+            respond_to_challenge::<BinaryMerkleTree<PoseidonHasher>>(&data, NODE_SIZE / 2, seed, k)
+                .expect("respond_to_challenge failure");
+        assert!(response.validate(seed, k, 2 * leaves));
+
+        assert!(respond_to_challenge::<BinaryMerkleTree<PoseidonHasher>>(
+            &data[1..],
+            NODE_SIZE,
+            seed,
+            k
+        )
+        .is_err());
+    }
+}
@@ -1,8 +1,15 @@
 use std::fs::OpenOptions;
 use std::io::Write;
 use std::path::Path;
+use std::time::Instant;
 
+use anyhow::{ensure, Result};
+use filecoin_hashers::Hasher;
 use memmap::{MmapMut, MmapOptions};
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::{drgraph::Graph, util::NODE_SIZE};
 
 pub fn setup_replica(data: &[u8], replica_path: &Path) -> MmapMut {
     let mut f = OpenOptions::new()
@@ -20,6 +27,63 @@ pub fn setup_replica(data: &[u8], replica_path: &Path) -> MmapMut {
     }
 }
 
+/// Asserts that `iterations` calls of `parents(node)` all return the same parents, so the graph
+/// carries no hidden state between calls.
+pub fn assert_parents_pure<H: Hasher, G: Graph<H>>(graph: &G, node: usize, iterations: usize) {
+    let mut expected = vec![0; graph.degree()];
+    graph.parents(node, &mut expected).expect("parents failed");
+
+    for iteration in 0..iterations {
+        let mut parents = vec![0; graph.degree()];
+        graph.parents(node, &mut parents).expect("parents failed");
+        assert_eq!(
+            parents, expected,
+            "different parents of node {} in iteration {}",
+            node, iteration
+        );
+    }
+}
+
+/// Returns `node_size` bytes of pseudo random data per node of `graph`, derived from `seed`.
+///
+/// For `NODE_SIZE` byte nodes, the two most significant bits of every node are cleared, like
+/// `bytes_into_fr_repr_safe` does, so each node is a valid field element. Smaller nodes are left
+/// as is, they already are once zero padded to `NODE_SIZE` bytes.
+pub fn test_data_for<H: Hasher, G: Graph<H>>(graph: &G, node_size: usize, seed: u64) -> Vec<u8> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut data = vec![0u8; graph.size() * node_size];
+    rng.fill_bytes(&mut data);
+    if node_size == NODE_SIZE {
+        for node in data.chunks_mut(NODE_SIZE) {
+            node[NODE_SIZE - 1] &= 0b0011_1111;
+        }
+    }
+
+    data
+}
+
+/// Measures how many nodes per second the parents of `graph` can be generated for, to estimate
+/// the cost of replication setup.
+///
+/// The parents of `sample_nodes` interior nodes, evenly spread over the graph, are computed.
+/// The node set is fixed for a given graph and sample count, only the timing varies.
+pub fn parents_throughput<H: Hasher, G: Graph<H>>(graph: &G, sample_nodes: usize) -> Result<f64> {
+    ensure!(sample_nodes > 0, "sample_nodes must be greater than zero");
+    ensure!(graph.size() > 2, "the graph has no interior nodes");
+
+    let interior = graph.size() - 2;
+    let mut parents = vec![0; graph.degree()];
+    let start = Instant::now();
+    for i in 0..sample_nodes {
+        let node = 2 + (i * interior / sample_nodes) % interior;
+        graph.parents(node, &mut parents)?;
+    }
+    // Avoid dividing by zero on coarse clocks.
+    let elapsed = start.elapsed().as_secs_f64().max(1e-9);
+
+    Ok(sample_nodes as f64 / elapsed)
+}
+
 #[macro_export]
 macro_rules! table_tests {
     ($property_test_func:ident {
@@ -8,7 +8,7 @@ use bellperson::{
 use ff::PrimeField;
 use merkletree::merkle::get_merkle_tree_row_count;
 
-use crate::{error::Error, settings::SETTINGS};
+use crate::{error::Error, merkle::MIN_NODE_SIZE, settings::SETTINGS};
 
 pub const NODE_SIZE: usize = 32;
 
@@ -19,14 +19,60 @@ pub fn data_at_node_offset(v: usize) -> usize {
 
 /// Returns the byte slice representing one node (of uniform size, NODE_SIZE) at position v in data.
 pub fn data_at_node(data: &[u8], v: usize) -> anyhow::Result<&[u8]> {
-    let offset = data_at_node_offset(v);
+    data_at_node_ref(data, v, NODE_SIZE)
+}
+
+/// Borrows the `node_size` bytes of node `index` from `data`, without copying.
+///
+/// Never panics: if `index * node_size` overflows, or the node ends past `data.len()`, an
+/// `Error::OutOfBounds` is returned instead.
+pub fn data_at_node_ref(data: &[u8], index: usize, node_size: usize) -> anyhow::Result<&[u8]> {
+    let end = index
+        .checked_mul(node_size)
+        .and_then(|offset| offset.checked_add(node_size))
+        .ok_or(Error::OutOfBounds(usize::MAX, data.len()))?;
+
+    ensure!(end <= data.len(), Error::OutOfBounds(end, data.len()));
+
+    Ok(&data[end - node_size..end])
+}
 
+/// Checks that `data`, chunked into nodes of `from_node_size` bytes, can be used as `nodes`
+/// nodes of `to_node_size` bytes, and returns it for use at the new node size.
+///
+/// Node sizes must be in `MIN_NODE_SIZE..=NODE_SIZE`, and `data` must hold a whole number of
+/// nodes of `from_node_size` bytes and exactly `nodes` nodes of `to_node_size` bytes. Nodes are
+/// contiguous, so the bytes are borrowed unchanged.
+pub fn rechunk(
+    data: &[u8],
+    from_node_size: usize,
+    to_node_size: usize,
+    nodes: usize,
+) -> anyhow::Result<&[u8]> {
+    for &node_size in &[from_node_size, to_node_size] {
+        ensure!(
+            (MIN_NODE_SIZE..=NODE_SIZE).contains(&node_size),
+            "unsupported node size {}, expected {}..={}",
+            node_size,
+            MIN_NODE_SIZE,
+            NODE_SIZE
+        );
+    }
     ensure!(
-        offset + NODE_SIZE <= data.len(),
-        Error::OutOfBounds(offset + NODE_SIZE, data.len())
+        data.len() % from_node_size == 0,
+        "data length {} is not a multiple of the node size {}",
+        data.len(),
+        from_node_size
+    );
+    ensure!(
+        nodes.checked_mul(to_node_size) == Some(data.len()),
+        "data length {} does not hold {} nodes of {} bytes",
+        data.len(),
+        nodes,
+        to_node_size
     );
 
-    Ok(&data[offset..offset + NODE_SIZE])
+    Ok(data)
 }
 
 /// Converts bytes into their bit representation, in little endian format.
@@ -255,6 +301,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_rechunk() {
+        let data = (0..128).map(|i| i as u8).collect::<Vec<_>>();
+
+        let rechunked = rechunk(&data, 16, 32, 4).expect("rechunk failure");
+        assert_eq!(rechunked, &data[..]);
+        assert_eq!(rechunked.as_ptr(), data.as_ptr());
+        assert_eq!(
+            rechunk(&data, 32, 16, 8).expect("rechunk failure"),
+            &data[..]
+        );
+        assert_eq!(
+            rechunk(&data, 32, 32, 4).expect("rechunk failure"),
+            &data[..]
+        );
+        assert_eq!(
+            rechunk(&data[..96], 32, 24, 4).expect("rechunk failure"),
+            &data[..96]
+        );
+        assert_eq!(
+            rechunk(&data, 16, 8, 16).expect("rechunk failure"),
+            &data[..]
+        );
+
+        // Three 16 byte nodes are not a whole number of 32 byte nodes.
+        assert!(rechunk(&data[..48], 16, 32, 1).is_err());
+        assert!(rechunk(&data[..48], 16, 32, 2).is_err());
+        // The data must hold exactly the expected number of nodes.
+        assert!(rechunk(&data, 16, 32, 3).is_err());
+        assert!(rechunk(&data, 16, 32, 5).is_err());
+        assert!(rechunk(&data, 16, 32, usize::MAX).is_err());
+        // 128 bytes are not a whole number of 24 byte nodes.
+        assert!(rechunk(&data, 16, 24, 5).is_err());
+
+        for &node_size in &[0, MIN_NODE_SIZE - 1, NODE_SIZE + 1, 64] {
+            assert!(rechunk(&data, node_size, 32, 4).is_err());
+            assert!(rechunk(&data, 32, node_size, 4).is_err());
+        }
+    }
+
     #[test]
     fn test_reverse_bit_numbering() {
         for _ in 0..100 {
@@ -332,4 +418,23 @@ mod tests {
             "circuit and non circuit do not match"
         );
     }
+
+    #[test]
+    fn test_data_at_node_ref() {
+        let data: Vec<u8> = (0..4 * NODE_SIZE).map(|i| i as u8).collect();
+
+        let last = data_at_node_ref(&data, 3, NODE_SIZE).expect("last node is in range");
+        assert_eq!(last, &data[3 * NODE_SIZE..]);
+        assert_eq!(last.as_ptr(), data[3 * NODE_SIZE..].as_ptr());
+        assert_eq!(data_at_node(&data, 3).expect("data_at_node failure"), last);
+        assert_eq!(
+            data_at_node_ref(&data, 7, 16).expect("last node is in range"),
+            &data[7 * 16..]
+        );
+
+        assert!(data_at_node_ref(&data, 4, NODE_SIZE).is_err());
+        assert!(data_at_node_ref(&data, 8, 16).is_err());
+        assert!(data_at_node_ref(&data, usize::MAX, NODE_SIZE).is_err());
+        assert!(data_at_node_ref(&data, usize::MAX / NODE_SIZE, NODE_SIZE).is_err());
+    }
 }
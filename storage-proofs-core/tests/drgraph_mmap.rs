@@ -0,0 +1,43 @@
+use filecoin_hashers::{poseidon::PoseidonHasher, sha256::Sha256Hasher, Hasher};
+use storage_proofs_core::{
+    api_version::ApiVersion,
+    drgraph::{BucketGraph, Graph, BASE_DEGREE},
+    merkle::{create_base_merkle_tree_with_node_size, BinaryMerkleTree, MerkleTreeTrait},
+    test_helper::{setup_replica, test_data_for},
+    util::NODE_SIZE,
+};
+use tempfile::tempdir;
+
+#[test]
+fn test_merkle_tree_from_mmap_poseidon() {
+    test_merkle_tree_from_mmap::<PoseidonHasher>();
+}
+
+#[test]
+fn test_merkle_tree_from_mmap_sha256() {
+    test_merkle_tree_from_mmap::<Sha256Hasher>();
+}
+
+fn test_merkle_tree_from_mmap<H: 'static + Hasher>() {
+    let nodes = 64;
+    let graph = BucketGraph::<H>::new(nodes, BASE_DEGREE, 0, [1; 32], ApiVersion::V1_1_0)
+        .expect("bucket graph new failed");
+
+    for &node_size in &[16, NODE_SIZE] {
+        let data = test_data_for(&graph, node_size, node_size as u64);
+
+        let dir = tempdir().expect("tempdir failure");
+        let mmap = setup_replica(&data, &dir.path().join("replica"));
+
+        let tree = graph
+            .merkle_tree_from_mmap(&mmap, node_size)
+            .expect("merkle_tree_from_mmap failure");
+        let expected =
+            create_base_merkle_tree_with_node_size::<BinaryMerkleTree<H>>(None, &data, node_size)
+                .expect("create_base_merkle_tree_with_node_size failure");
+        assert_eq!(tree.root(), expected.root());
+
+        let short = setup_replica(&data[node_size..], &dir.path().join("short"));
+        assert!(graph.merkle_tree_from_mmap(&short, node_size).is_err());
+    }
+}
@@ -137,7 +137,7 @@ impl<Tree: MerkleTreeTrait, G: 'static + Hasher> Proof<Tree, G> {
         // -- verify replica column openings
 
         // Private Inputs for the DRG parent nodes.
-        let mut drg_parents = Vec::with_capacity(layers);
+        let mut drg_parents = Vec::with_capacity(drg_parents_proofs.len());
 
         for (i, parent) in drg_parents_proofs.into_iter().enumerate() {
             let (parent_col, inclusion_path) =
@@ -157,7 +157,7 @@ impl<Tree: MerkleTreeTrait, G: 'static + Hasher> Proof<Tree, G> {
         }
 
         // Private Inputs for the Expander parent nodes.
-        let mut exp_parents = Vec::new();
+        let mut exp_parents = Vec::with_capacity(exp_parents_proofs.len());
 
         for (i, parent) in exp_parents_proofs.into_iter().enumerate() {
             let (parent_col, inclusion_path) =
@@ -191,7 +191,7 @@ impl<Tree: MerkleTreeTrait, G: 'static + Hasher> Proof<Tree, G> {
             let mut cs = cs.namespace(|| format!("labeling_{}", layer));
 
             // Collect the parents
-            let mut parents = Vec::new();
+            let mut parents = Vec::with_capacity(drg_parents.len() + exp_parents.len());
 
             // all layers have drg parents
             for parent_col in &drg_parents {
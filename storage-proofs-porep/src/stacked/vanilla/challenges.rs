@@ -1,6 +1,9 @@
+use std::collections::HashSet;
+
+use anyhow::{ensure, Result};
 use filecoin_hashers::Domain;
 use num_bigint::BigUint;
-use num_traits::cast::ToPrimitive;
+use num_traits::{cast::ToPrimitive, Zero};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
@@ -49,14 +52,7 @@ impl LayerChallenges {
         (0..challenges_count)
             .map(|i| {
                 let j: u32 = ((challenges_count * k as usize) + i) as u32;
-
-                let hash = Sha256::new()
-                    .chain(replica_id.into_bytes())
-                    .chain(seed)
-                    .chain(&j.to_le_bytes())
-                    .finalize();
-
-                let big_challenge = BigUint::from_bytes_le(hash.as_ref());
+                let big_challenge = challenge_digest(replica_id, seed, j);
 
                 // We cannot try to prove the first node, so make sure the challenge
                 // can never be 0.
@@ -68,6 +64,107 @@ impl LayerChallenges {
             })
             .collect()
     }
+
+    /// Derive all challenges, distributed over the leaves according to `weight`. The first
+    /// node is never selected.
+    ///
+    /// `ChallengeWeight::Uniform` yields exactly the challenges of [`LayerChallenges::derive`].
+    /// `ChallengeWeight::LinearRecent` yields distinct challenges, and requires twice as many
+    /// challengeable nodes as challenges, so that drawing a distinct one stays cheap.
+    pub fn derive_weighted<D: Domain>(
+        &self,
+        leaves: usize,
+        replica_id: &D,
+        seed: &[u8; 32],
+        k: u8,
+        weight: ChallengeWeight,
+    ) -> Result<Vec<usize>> {
+        ensure!(leaves > 2, "Too few leaves: {}", leaves);
+
+        match weight {
+            ChallengeWeight::Uniform => Ok(self.derive(leaves, replica_id, seed, k)),
+            ChallengeWeight::LinearRecent => {
+                let challenges_count = self.challenges_count_all();
+                ensure!(
+                    challenges_count <= (leaves - 1) / 2,
+                    "Too few leaves for {} distinct challenges: {}",
+                    challenges_count,
+                    leaves
+                );
+
+                let challengeable = BigUint::from(leaves as u64 - 1);
+                let modulus = &challengeable * &challengeable;
+
+                let mut seen = HashSet::with_capacity(challenges_count);
+                let mut challenges = Vec::with_capacity(challenges_count);
+                for i in 0..challenges_count {
+                    let j: u32 = ((challenges_count * k as usize) + i) as u32;
+
+                    // Redraw from the digest of the previous attempt until the challenge is
+                    // distinct, the first attempt is the same digest `derive` uses.
+                    let mut digest = challenge_digest(replica_id, seed, j);
+                    loop {
+                        // Taking the integer square root of a value uniform in `[0, n^2)` selects
+                        // `x` in `[0, n)` with probability `(2x + 1) / n^2`, i.e. linearly
+                        // increasing with the node index.
+                        //
+                        // The square is only smaller than `u64::MAX` for small sectors, so the
+                        // root is taken on the `BigUint`. It is smaller than `leaves - 1` and
+                        // hence always fits a `usize`.
+                        let root = integer_sqrt(&(&digest % &modulus));
+                        let challenge = root
+                            .to_usize()
+                            .expect("the root is smaller than the number of leaves")
+                            + 1;
+                        if seen.insert(challenge) {
+                            challenges.push(challenge);
+                            break;
+                        }
+
+                        digest =
+                            BigUint::from_bytes_le(Sha256::digest(&digest.to_bytes_le()).as_ref());
+                    }
+                }
+
+                Ok(challenges)
+            }
+        }
+    }
+}
+
+/// The distribution used to select challenged nodes, see [`LayerChallenges::derive_weighted`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChallengeWeight {
+    /// Every node (except the first) is equally likely to be challenged.
+    Uniform,
+    /// The probability of challenging a node grows linearly with its index, biasing challenges
+    /// towards the most recently written nodes.
+    LinearRecent,
+}
+
+fn challenge_digest<D: Domain>(replica_id: &D, seed: &[u8; 32], j: u32) -> BigUint {
+    let hash = Sha256::new()
+        .chain(replica_id.into_bytes())
+        .chain(seed)
+        .chain(&j.to_le_bytes())
+        .finalize();
+
+    BigUint::from_bytes_le(hash.as_ref())
+}
+
+/// Returns the largest `root` with `root * root <= n`, by Newton's method.
+fn integer_sqrt(n: &BigUint) -> BigUint {
+    if n.is_zero() {
+        return BigUint::zero();
+    }
+
+    let mut root = n.clone();
+    let mut next = (&root + 1u32) >> 1;
+    while next < root {
+        root = next;
+        next = (&root + n / &root) >> 1;
+    }
+    root
 }
 
 #[derive(Debug, Default)]
@@ -160,4 +257,95 @@ mod test {
             assert_eq!(one_partition_challenges, many_partition_challenges);
         }
     }
+
+    #[test]
+    fn weighted_challenge_derivation() {
+        let n = 100;
+        let leaves = 1 << 10;
+        let rng = &mut thread_rng();
+        let replica_id: Sha256Domain = Sha256Domain::random(rng);
+        let challenges = LayerChallenges::new(1, n);
+
+        let mut uniform_sum = 0;
+        let mut recent_sum = 0;
+        for _ in 0..20 {
+            let seed: [u8; 32] = rng.gen();
+
+            let uniform = challenges
+                .derive_weighted(leaves, &replica_id, &seed, 0, ChallengeWeight::Uniform)
+                .expect("derive_weighted failure");
+            assert_eq!(uniform, challenges.derive(leaves, &replica_id, &seed, 0));
+
+            let recent = challenges
+                .derive_weighted(leaves, &replica_id, &seed, 0, ChallengeWeight::LinearRecent)
+                .expect("derive_weighted failure");
+            assert_eq!(recent.len(), n);
+            assert_eq!(recent.iter().collect::<HashSet<_>>().len(), n);
+            assert!(recent.iter().all(|&c| c > 0 && c < leaves));
+
+            uniform_sum += uniform.iter().sum::<usize>();
+            recent_sum += recent.iter().sum::<usize>();
+        }
+
+        // The expected mean index is `leaves / 2` for uniform and `2 * leaves / 3` for linearly
+        // weighted challenges.
+        assert!(recent_sum > uniform_sum);
+    }
+
+    #[test]
+    fn test_integer_sqrt() {
+        for n in 0..1000u64 {
+            let root = integer_sqrt(&BigUint::from(n))
+                .to_u64()
+                .expect("small root");
+            assert!(root * root <= n);
+            assert!((root + 1) * (root + 1) > n);
+        }
+
+        // Squares of more than 64 bits, as for sectors of more than `2^32` leaves.
+        let large = BigUint::from(u64::MAX) * BigUint::from(3u32);
+        assert_eq!(integer_sqrt(&(&large * &large)), large);
+        assert_eq!(integer_sqrt(&(&large * &large - 1u32)), &large - 1u32);
+    }
+
+    #[test]
+    fn test_derive_weighted_large_sector() {
+        let leaves = (1usize << 40) + 3;
+        let replica_id = Sha256Domain::random(&mut thread_rng());
+        let challenges = LayerChallenges::new(1, 64)
+            .derive_weighted(
+                leaves,
+                &replica_id,
+                &[7; 32],
+                0,
+                ChallengeWeight::LinearRecent,
+            )
+            .expect("derive_weighted failure");
+        assert!(challenges.iter().all(|&c| c > 0 && c < leaves));
+    }
+
+    #[test]
+    fn test_derive_weighted_distinct() {
+        let leaves = 65;
+        let rng = &mut thread_rng();
+        let replica_id: Sha256Domain = Sha256Domain::random(rng);
+        let seed: [u8; 32] = rng.gen();
+
+        // Challenging half of the challengeable nodes, many draws collide.
+        let challenges = LayerChallenges::new(1, 32)
+            .derive_weighted(leaves, &replica_id, &seed, 0, ChallengeWeight::LinearRecent)
+            .expect("derive_weighted failure");
+        assert_eq!(challenges.len(), 32);
+        assert_eq!(challenges.iter().collect::<HashSet<_>>().len(), 32);
+        assert!(challenges.iter().all(|&c| c > 0 && c < leaves));
+
+        for &weight in &[ChallengeWeight::Uniform, ChallengeWeight::LinearRecent] {
+            assert!(LayerChallenges::new(1, 1)
+                .derive_weighted(2, &replica_id, &seed, 0, weight)
+                .is_err());
+        }
+        assert!(LayerChallenges::new(1, 33)
+            .derive_weighted(leaves, &replica_id, &seed, 0, ChallengeWeight::LinearRecent)
+            .is_err());
+    }
 }
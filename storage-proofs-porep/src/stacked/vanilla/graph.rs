@@ -2,7 +2,7 @@ use std::convert::{TryFrom, TryInto};
 use std::fmt::{self, Debug, Formatter};
 use std::marker::PhantomData;
 
-use anyhow::ensure;
+use anyhow::{ensure, format_err};
 use filecoin_hashers::Hasher;
 use log::info;
 use sha2raw::Sha256;
@@ -309,6 +309,26 @@ where
         self.base_graph().seed()
     }
 
+    /// Checks the base parents like `Graph::verify_parents`. Expansion parents are nodes of the
+    /// previous layer, so they are only checked to be in range.
+    fn verify_parents(&self) -> Result<()> {
+        self.base_graph().verify_parents()?;
+
+        let mut parents = vec![0; self.expansion_degree()];
+        for node in 0..self.size() {
+            self.expanded_parents(node, &mut parents)?;
+            if let Some(parent) = parents.iter().find(|&&p| p as usize >= self.size()) {
+                return Err(format_err!(
+                    "node {} has the out of range expansion parent {}",
+                    node,
+                    parent
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
     fn new(
         nodes: usize,
         base_degree: usize,
@@ -429,6 +449,22 @@ where
         self.generate_expanded_parents(node, parents);
         Ok(())
     }
+
+    /// Returns the base and the expansion parents of `node` as separate lists, each sorted.
+    ///
+    /// Merging both lists yields the same parents as `parents`, which keeps them in the order
+    /// required for labeling instead.
+    pub fn parents_split(&self, node: usize) -> Result<(Vec<u32>, Vec<u32>)> {
+        let mut base_parents = vec![0; self.base_graph().degree()];
+        self.base_parents(node, &mut base_parents)?;
+        base_parents.sort_unstable();
+
+        let mut exp_parents = vec![0; self.expansion_degree()];
+        self.expanded_parents(node, &mut exp_parents)?;
+        exp_parents.sort_unstable();
+
+        Ok((base_parents, exp_parents))
+    }
 }
 
 impl<H, G> PartialEq for StackedGraph<H, G>
@@ -456,6 +492,52 @@ mod tests {
 
     use filecoin_hashers::poseidon::PoseidonHasher;
 
+    #[test]
+    fn test_verify_parents() {
+        let graph = StackedBucketGraph::<PoseidonHasher>::new_stacked(
+            256,
+            BASE_DEGREE,
+            EXP_DEGREE,
+            [3; 32],
+            ApiVersion::V1_1_0,
+        )
+        .expect("stacked bucket graph new_stacked failed");
+
+        graph
+            .verify_parents()
+            .expect("stacked graph parents are malformed");
+    }
+
+    #[test]
+    fn test_parents_split() {
+        let nodes = 64;
+        let graph = StackedBucketGraph::<PoseidonHasher>::new_stacked(
+            nodes,
+            BASE_DEGREE,
+            EXP_DEGREE,
+            [7; 32],
+            ApiVersion::V1_1_0,
+        )
+        .expect("stacked bucket graph new_stacked failed");
+
+        for node in 0..nodes {
+            let (base_parents, exp_parents) =
+                graph.parents_split(node).expect("parents_split failed");
+            assert_eq!(base_parents.len(), BASE_DEGREE);
+            assert_eq!(exp_parents.len(), EXP_DEGREE);
+
+            let mut merged = base_parents;
+            merged.extend(exp_parents);
+            merged.sort_unstable();
+
+            let mut parents = vec![0; DEGREE];
+            graph.parents(node, &mut parents).expect("parents failed");
+            parents.sort_unstable();
+
+            assert_eq!(merged, parents);
+        }
+    }
+
     // Test that 3 (or more) rounds of the Feistel cipher can be used
     // as a pseudorandom permutation, that is, each input will be mapped
     // to a unique output (and though not test here, since the cipher
@@ -680,4 +762,86 @@ mod tests {
 
         assert!(success);
     }
+
+    #[test]
+    fn test_children() {
+        let nodes = 64;
+        let graph = StackedBucketGraph::<PoseidonHasher>::new_stacked(
+            nodes,
+            BASE_DEGREE,
+            EXP_DEGREE,
+            [5; 32],
+            ApiVersion::V1_1_0,
+        )
+        .expect("stacked bucket graph new_stacked failed");
+
+        // The children of every node, straight from the parents.
+        let mut expected = vec![Vec::new(); nodes];
+        let mut parents = vec![0; graph.degree()];
+        for node in 0..nodes {
+            graph.parents(node, &mut parents).expect("parents failed");
+            for &parent in &parents {
+                let parent = parent as usize;
+                if parent != node && !expected[parent].contains(&node) {
+                    expected[parent].push(node);
+                }
+            }
+        }
+
+        // Expansion parents may follow their children.
+        assert!(expected
+            .iter()
+            .enumerate()
+            .any(|(node, children)| children.iter().any(|&child| child < node)));
+
+        let index = graph.build_child_index().expect("build_child_index failed");
+        for (node, (expected, children)) in expected.iter_mut().zip(index.iter()).enumerate() {
+            expected.sort_unstable();
+            assert_eq!(&graph.children(node).expect("children failed"), expected);
+            assert_eq!(children, expected);
+        }
+    }
+
+    #[test]
+    fn test_descendants() {
+        let nodes = 64;
+        let graph = StackedBucketGraph::<PoseidonHasher>::new_stacked(
+            nodes,
+            BASE_DEGREE,
+            EXP_DEGREE,
+            [6; 32],
+            ApiVersion::V1_1_0,
+        )
+        .expect("stacked bucket graph new_stacked failed");
+
+        let mut parents = vec![vec![0; graph.degree()]; nodes];
+        for (node, parents) in parents.iter_mut().enumerate() {
+            graph.parents(node, parents).expect("parents failed");
+        }
+
+        for node in 0..nodes {
+            // Grow the set of dependent nodes until it is closed under the parent relation.
+            let mut expected = std::collections::BTreeSet::new();
+            loop {
+                let before = expected.len();
+                for (child, parents) in parents.iter().enumerate() {
+                    // Self references mark nodes without parents.
+                    let depends = parents.iter().map(|&parent| parent as usize).any(|parent| {
+                        parent != child && (parent == node || expected.contains(&parent))
+                    });
+                    if depends {
+                        expected.insert(child);
+                    }
+                }
+                if expected.len() == before {
+                    break;
+                }
+            }
+
+            assert_eq!(
+                graph.descendants(node).expect("descendants failed"),
+                expected
+            );
+        }
+    }
 }